@@ -2,7 +2,7 @@ use std::io;
 
 fn main() {
     let mut out = io::stdout();
-    for fd in fdwalk::walk(".").with_open() {
+    for fd in fdwalk::walk(".").with_open().readahead(16) {
         let fd = fd.unwrap();
         io::copy(&mut fd.open().unwrap(), &mut out).unwrap();
     }
@@ -0,0 +1,134 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::{Entry, Walk, WalkError};
+
+/// The path of the entry the walk is at, kept up to date by `Walk::next` for
+/// `Walk::with_borrowed_paths`.
+pub(crate) struct ScratchPath {
+    root: OsString,
+    buf: Vec<u8>,
+    /// The length of `buf` up to the segment at each depth.
+    lens: Vec<usize>,
+}
+
+impl ScratchPath {
+    /// Replace everything at `depth` and below with `segment`. Entries are popped depth-first, so
+    /// what is left above is the parent of the new entry.
+    pub(crate) fn enter(&mut self, depth: usize, segment: &OsStr) {
+        self.lens.truncate(depth);
+        if depth == 0 {
+            self.buf.clear();
+            self.buf.extend_from_slice(self.root.as_bytes());
+            self.lens.push(self.buf.len());
+            if self.buf.is_empty() {
+                // like `with_paths`, `walk(".")` yields `foo` instead of `./foo`
+                self.buf.push(b'.');
+            }
+            return;
+        }
+
+        self.buf.truncate(self.lens[depth - 1]);
+        if !self.buf.is_empty() && self.buf.last() != Some(&b'/') {
+            self.buf.push(b'/');
+        }
+        self.buf.extend_from_slice(segment.as_bytes());
+        self.lens.push(self.buf.len());
+    }
+
+    fn as_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(&self.buf))
+    }
+}
+
+/// An entry together with its path, returned by `BorrowedPaths::next_ref`.
+pub struct BorrowedEntry<'a, N> {
+    path: &'a Path,
+    entry: N,
+}
+
+impl<'a, N> BorrowedEntry<'a, N> {
+    /// The path of the entry, valid until the next call to `next_ref`.
+    pub fn path(&self) -> &Path {
+        self.path
+    }
+
+    /// The entry itself.
+    pub fn entry(&self) -> &N {
+        &self.entry
+    }
+
+    /// Drop the path and return the entry.
+    pub fn into_entry(self) -> N {
+        self.entry
+    }
+}
+
+/// A walk that builds the path of each entry in a single buffer, returned by
+/// `Walk::with_borrowed_paths`.
+pub struct BorrowedPaths<N: Entry> {
+    walk: Walk<N>,
+}
+
+impl<N: Entry> BorrowedPaths<N> {
+    /// Return the next entry with its path. This is like `Iterator::next`, except that the path
+    /// borrows from the walk, and so must be dropped before the next call.
+    pub fn next_ref(&mut self) -> Option<Result<BorrowedEntry<'_, N>, WalkError>> {
+        let entry = match self.walk.next()? {
+            Ok(x) => x,
+            Err(e) => return Some(Err(e)),
+        };
+        let path = self.walk.scratch.as_ref().unwrap().as_path();
+        Some(Ok(BorrowedEntry { path, entry }))
+    }
+
+    /// Stop borrowing paths and continue as a regular walk.
+    pub fn into_inner(mut self) -> Walk<N> {
+        self.walk.scratch = None;
+        self.walk
+    }
+}
+
+impl<N: Entry> Walk<N> {
+    /// Keep the path of the current entry in one buffer that is reused for every entry, instead
+    /// of tracking paths with `with_paths`. Nothing is allocated per entry, but paths can only
+    /// be borrowed until the next entry is returned, so `Iterator` can't be used.
+    ///
+    /// Panics if the walk has already been started, since the paths of the entries that are left
+    /// would be unknown.
+    ///
+    /// ```rust
+    /// let mut walk = fdwalk::walk(".").with_borrowed_paths();
+    /// while let Some(entry) = walk.next_ref() {
+    ///     println!("{}", entry.unwrap().path().display());
+    /// }
+    /// ```
+    pub fn with_borrowed_paths(mut self) -> BorrowedPaths<N> {
+        let started = match self.walk_stack[..] {
+            [ref root] => root.depth != 0,
+            _ => true,
+        };
+        assert!(
+            !started,
+            "with_borrowed_paths called on a walk that was started"
+        );
+
+        let root = match self.options.relative_to {
+            Some(ref base) => crate::relative_root(&self.path, base),
+            None => PathBuf::from(&self.path),
+        };
+        // spelled the same as by `with_paths`
+        let root = if root.as_os_str() == "." {
+            OsString::new()
+        } else {
+            root.components().collect::<PathBuf>().into_os_string()
+        };
+        self.scratch = Some(ScratchPath {
+            root,
+            buf: Vec::new(),
+            lens: Vec::new(),
+        });
+        BorrowedPaths { walk: self }
+    }
+}
@@ -0,0 +1,152 @@
+use std::ffi::OsStr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+
+use nix::dir::{Dir, Type};
+use nix::errno::Errno;
+use nix::fcntl::AtFlags;
+use nix::libc;
+use nix::sys::stat::{fstat, fstatat, FileStat};
+
+use crate::segment::Segment;
+use crate::{stat_type, Dirent, Entry};
+
+/// An entry that keeps nothing but its name, for walks that only count entries.
+///
+/// Names of up to 23 bytes are stored inline, so for most entries this allocates nothing. Name
+/// filters and globs work like with any other entry.
+///
+/// ```rust
+/// let count = fdwalk::walk_with::<fdwalk::CountOnly, _>(".").count();
+/// println!("{} files", count);
+/// ```
+pub struct CountOnly {
+    segment: Segment,
+}
+
+impl Entry for CountOnly {
+    fn root(_parent_dir: Option<&Arc<Dir>>, segment: &OsStr) -> Self {
+        CountOnly {
+            segment: segment.into(),
+        }
+    }
+
+    fn segment(&self) -> &OsStr {
+        self.segment.as_os_str()
+    }
+
+    fn new_child(&self, _parent_dir: &Arc<Dir>, segment: &OsStr, _dirent: &Dirent) -> Self {
+        CountOnly {
+            segment: segment.into(),
+        }
+    }
+}
+
+/// What `Walk::count_files` walks with: just enough to tell the type of each entry that is
+/// yielded, and to `stat()` it for filters.
+pub(crate) struct Counted {
+    segment: Segment,
+    parent_dir: Option<Arc<Dir>>,
+    /// The directory itself, once it has been opened.
+    own_dir: Option<Arc<Dir>>,
+    /// `d_type`, or the type the walk found out while yielding this entry.
+    file_type: Option<Type>,
+    /// Whether `file_type` was set by the walk, and not from `d_type`.
+    marked: bool,
+    /// A directory that could not be opened, see `Walk::report_unreadable_dirs`.
+    unreadable: bool,
+    /// 0 for the root of the walk.
+    depth: usize,
+}
+
+impl Counted {
+    pub(crate) fn is_unreadable_root(&self) -> bool {
+        self.unreadable && self.depth == 0
+    }
+
+    pub(crate) fn is_unreadable(&self) -> bool {
+        self.unreadable
+    }
+
+    fn dir_fd(&self) -> RawFd {
+        self.parent_dir
+            .as_ref()
+            .map(|x| x.as_raw_fd())
+            .unwrap_or(libc::AT_FDCWD)
+    }
+
+    /// The type this entry is counted as, which needs a `stat()` if `d_type` was inconclusive or
+    /// a symlink has been followed.
+    pub(crate) fn counted_type(&self, follow_symlinks: bool) -> Type {
+        match self.file_type {
+            Some(x) if self.marked => x,
+            Some(Type::Symlink) if !follow_symlinks => Type::Symlink,
+            Some(Type::Symlink) | None => {
+                let flags = if follow_symlinks {
+                    AtFlags::empty()
+                } else {
+                    AtFlags::AT_SYMLINK_NOFOLLOW
+                };
+                match fstatat(self.dir_fd(), self.segment.as_os_str(), flags) {
+                    Ok(stat) => stat_type(&stat),
+                    // vanished in the meantime, count it as what it was last seen as
+                    Err(_) => self.file_type.unwrap_or(Type::File),
+                }
+            }
+            Some(x) => x,
+        }
+    }
+}
+
+impl Entry for Counted {
+    fn root(parent_dir: Option<&Arc<Dir>>, segment: &OsStr) -> Self {
+        Counted {
+            segment: segment.into(),
+            parent_dir: parent_dir.cloned(),
+            own_dir: None,
+            file_type: None,
+            marked: false,
+            unreadable: false,
+            depth: 0,
+        }
+    }
+
+    fn segment(&self) -> &OsStr {
+        self.segment.as_os_str()
+    }
+
+    fn stat(&self) -> Option<Result<FileStat, Errno>> {
+        Some(match self.own_dir {
+            Some(ref dir) => fstat(dir.as_raw_fd()),
+            None => fstatat(
+                self.dir_fd(),
+                self.segment.as_os_str(),
+                AtFlags::AT_SYMLINK_NOFOLLOW,
+            ),
+        })
+    }
+
+    fn new_child(&self, parent_dir: &Arc<Dir>, segment: &OsStr, dirent: &Dirent) -> Self {
+        Counted {
+            segment: segment.into(),
+            parent_dir: Some(parent_dir.clone()),
+            own_dir: None,
+            file_type: dirent.file_type(),
+            marked: false,
+            unreadable: false,
+            depth: self.depth + 1,
+        }
+    }
+
+    fn mark_directory(&mut self, dir: Option<&Arc<Dir>>) {
+        self.file_type = Some(Type::Directory);
+        self.marked = true;
+        self.own_dir = dir.cloned();
+        self.unreadable = dir.is_none();
+    }
+
+    fn mark_symlink(&mut self) {
+        self.file_type = Some(Type::Symlink);
+        self.marked = true;
+    }
+}
@@ -1,35 +1,102 @@
 //! TODO
 
-use nix::dir::Dir;
+use nix::dir::{Dir, Type};
 use nix::errno::Errno;
-use nix::fcntl::{openat, AtFlags, OFlag};
-use nix::sys::stat::{fstatat, FileStat, Mode};
-use std::ffi::{OsStr, OsString};
+use nix::fcntl::{fcntl, openat, readlinkat, AtFlags, FcntlArg, OFlag};
+use nix::libc;
+use nix::sys::stat::{fchmodat, fstat, fstatat, FchmodatFlags, FileStat, Mode, SFlag};
+use nix::sys::statvfs::{fstatvfs, statvfs, FsFlags, Statvfs};
+use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ffi::OsStr;
 use std::fs::File;
-use std::os::unix::io::{AsRawFd, FromRawFd};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::mem::ManuallyDrop;
+#[cfg(feature = "memmap")]
+use std::ops::Deref;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::Entry;
+use crate::segment::Segment;
+use crate::{walk_fd, Dirent, Entry, SharedDir, TreeHashInput, Walk};
 
 #[derive(Debug)]
 struct PathEntryInner {
     parent: Option<WithPath>,
-    segment: OsString,
+    segment: Segment,
+}
+
+impl Drop for PathEntryInner {
+    fn drop(&mut self) {
+        // Unlink the parents one by one. Letting the compiler drop them recursively overflows the
+        // stack for very deep paths.
+        let mut parent = self.parent.take();
+        while let Some(WithPath(inner)) = parent {
+            parent = match Arc::try_unwrap(inner) {
+                Ok(mut inner) => inner.parent.take(),
+                // still in use by someone else
+                Err(_) => None,
+            };
+        }
+    }
 }
 
 /// TODO currently useless
 pub trait OpenProvider {
-    /// Store the parent directory in a new instance (or don't...)
-    fn dir_provider(dir: Arc<Dir>) -> Self;
+    /// Store the parent directory in a new instance (or don't...). There is no parent directory
+    /// for the root of a walk that was started from a path.
+    fn dir_provider(dir: Option<&Arc<Dir>>) -> Self;
+
+    /// Store the directory this entry has been opened as (or don't...)
+    fn set_own_dir(&mut self, _dir: &Arc<Dir>) {}
+
+    /// `stat()` an entry, if this provider has a way to do that.
+    fn stat_entry<P: PathProvider, E: DirentProvider>(
+        _entry: &FileEntry<Self, P, E>,
+    ) -> Option<Result<FileStat, Errno>>
+    where
+        Self: Sized,
+    {
+        None
+    }
 }
 
 /// A type parameter for `FileEntry` that is used to store parent FD.
-pub struct WithOpen(Arc<Dir>);
+pub struct WithOpen {
+    parent_dir: Option<SharedDir>,
+    /// For directories, the directory itself.
+    own_dir: Option<SharedDir>,
+    stat: OnceCell<FileStat>,
+    /// `file_id()` as used by `PartialEq` and `Hash`, fixed on first use. `None` if it failed.
+    id: OnceCell<Option<(u64, u64)>>,
+}
 
 impl OpenProvider for WithOpen {
-    fn dir_provider(dir: Arc<Dir>) -> Self {
-        WithOpen(dir)
+    fn dir_provider(dir: Option<&Arc<Dir>>) -> Self {
+        WithOpen {
+            parent_dir: dir.cloned().map(SharedDir::from),
+            own_dir: None,
+            stat: OnceCell::new(),
+            id: OnceCell::new(),
+        }
+    }
+
+    fn set_own_dir(&mut self, dir: &Arc<Dir>) {
+        self.own_dir = Some(SharedDir::from(dir.clone()));
+    }
+
+    fn stat_entry<P: PathProvider, E: DirentProvider>(
+        entry: &FileEntry<Self, P, E>,
+    ) -> Option<Result<FileStat, Errno>> {
+        Some(entry.stat())
     }
 
     // TODO: open() and friends should exist here, meaning OpenProviders should keep track of basename.
@@ -40,7 +107,7 @@ impl OpenProvider for WithOpen {
 pub struct WithoutOpen;
 
 impl OpenProvider for WithoutOpen {
-    fn dir_provider(_dir: Arc<Dir>) -> Self {
+    fn dir_provider(_dir: Option<&Arc<Dir>>) -> Self {
         WithoutOpen
     }
 }
@@ -50,6 +117,11 @@ impl OpenProvider for WithoutOpen {
 pub trait PathProvider: Sized {
     /// Construct a "child entry" from a given one + a path segment.
     fn path_provider(base: Option<&Self>, segment: &OsStr) -> Self;
+
+    /// The full path of an entry, if it is tracked. `node` is `None` for a root of `.`.
+    fn to_path(_node: Option<&Self>) -> Option<PathBuf> {
+        None
+    }
 }
 
 /// A type parameter for `FileEntry` that is used to keep track of paths.
@@ -63,10 +135,128 @@ pub struct WithPath(Arc<PathEntryInner>);
 impl PathProvider for WithPath {
     fn path_provider(base: Option<&Self>, segment: &OsStr) -> Self {
         WithPath(Arc::new(PathEntryInner {
-            parent: base.map(|x| x.clone()),
-            segment: segment.to_owned(),
+            parent: base.cloned(),
+            segment: segment.into(),
+        }))
+    }
+
+    fn to_path(node: Option<&Self>) -> Option<PathBuf> {
+        // XXX: slow, also self.segment apparently == self.parent_node.segment?
+        let mut segments = vec![];
+        let mut capacity = 0;
+
+        let mut current_opt: Option<&WithPath> = node;
+
+        while let Some(ref mut current) = current_opt {
+            segments.push(current.0.segment.as_os_str());
+            // one extra byte for the separator
+            capacity += current.0.segment.len() + 1;
+            current_opt = current.0.parent.as_ref();
+        }
+
+        let mut rv = PathBuf::with_capacity(capacity);
+
+        for segment in segments.into_iter().rev() {
+            rv.push(segment);
+        }
+
+        if rv.as_os_str().is_empty() {
+            // the root of `walk(".")`
+            rv.push(".");
+        }
+
+        Some(rv)
+    }
+}
+
+/// A type parameter for `FileEntry` that keeps track of paths like `WithPath`, but stores each
+/// distinct path segment only once per process, see `Walk::with_interned_paths`.
+#[derive(Debug, Clone)]
+pub struct WithInternedPath(Arc<InternedPathEntry>);
+
+#[derive(Debug)]
+struct InternedPathEntry {
+    parent: Option<WithInternedPath>,
+    /// Only dropped by `drop`, while holding the lock of `interned_segments`.
+    segment: ManuallyDrop<Arc<OsStr>>,
+}
+
+/// All segments currently in use by `WithInternedPath`.
+fn interned_segments() -> MutexGuard<'static, HashSet<Arc<OsStr>>> {
+    static SEGMENTS: OnceLock<Mutex<HashSet<Arc<OsStr>>>> = OnceLock::new();
+    SEGMENTS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+impl Drop for InternedPathEntry {
+    fn drop(&mut self) {
+        {
+            // Segments are only cloned and dropped while holding the lock, so the count is
+            // accurate. Once ours is released, the last reference is the set's.
+            let mut segments = interned_segments();
+            // SAFETY: `segment` is not touched again after this
+            let segment = unsafe { ManuallyDrop::take(&mut self.segment) };
+            if Arc::strong_count(&segment) == 2 {
+                segments.remove(&segment);
+            }
+            // released before the lock, unlike a field dropped after `drop` returns
+            drop(segment);
+        }
+
+        // see `PathEntryInner`
+        let mut parent = self.parent.take();
+        while let Some(WithInternedPath(inner)) = parent {
+            parent = match Arc::try_unwrap(inner) {
+                Ok(mut inner) => inner.parent.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl PathProvider for WithInternedPath {
+    fn path_provider(base: Option<&Self>, segment: &OsStr) -> Self {
+        let segment = {
+            let mut segments = interned_segments();
+            match segments.get(segment) {
+                Some(x) => x.clone(),
+                None => {
+                    let x: Arc<OsStr> = segment.into();
+                    segments.insert(x.clone());
+                    x
+                }
+            }
+        };
+
+        WithInternedPath(Arc::new(InternedPathEntry {
+            parent: base.cloned(),
+            segment: ManuallyDrop::new(segment),
         }))
     }
+
+    fn to_path(node: Option<&Self>) -> Option<PathBuf> {
+        let mut segments = vec![];
+        let mut capacity = 0;
+        let mut current_opt = node;
+
+        while let Some(current) = current_opt {
+            segments.push(&**current.0.segment);
+            capacity += current.0.segment.len() + 1;
+            current_opt = current.0.parent.as_ref();
+        }
+
+        let mut rv = PathBuf::with_capacity(capacity);
+        for segment in segments.into_iter().rev() {
+            rv.push(segment);
+        }
+        if rv.as_os_str().is_empty() {
+            rv.push(".");
+        }
+
+        Some(rv)
+    }
 }
 
 /// A type parameter for `FileEntry` to avoid storing paths.
@@ -78,22 +268,344 @@ impl PathProvider for WithoutPath {
     }
 }
 
+/// A dirent provider is a type that can be used to keep the directory entry returned by
+/// `getdents` around.
+pub trait DirentProvider {
+    /// Store the directory entry in a new instance (or don't...)
+    fn dirent_provider(dirent: &Dirent) -> Self;
+
+    /// The file type reported by `getdents`, if it was stored.
+    fn file_type(&self) -> Option<Type>;
+}
+
+/// A type parameter for `FileEntry` that is used to store the raw directory entry, see
+/// `FileEntry::dirent`.
+pub struct WithDirent(Dirent);
+
+impl DirentProvider for WithDirent {
+    fn dirent_provider(dirent: &Dirent) -> Self {
+        WithDirent(*dirent)
+    }
+
+    fn file_type(&self) -> Option<Type> {
+        self.0.file_type()
+    }
+}
+
+/// A type parameter for `FileEntry` to avoid storing the raw directory entry.
+pub struct WithoutDirent;
+
+impl DirentProvider for WithoutDirent {
+    fn dirent_provider(_dirent: &Dirent) -> Self {
+        WithoutDirent
+    }
+
+    fn file_type(&self) -> Option<Type> {
+        None
+    }
+}
+
+/// A read-only memory map of a file, returned by `FileEntry::mmap`.
+///
+/// Empty files cannot be mapped, for those this derefs to an empty slice.
+#[cfg(feature = "memmap")]
+pub struct Mmap(Option<memmap2::Mmap>);
+
+#[cfg(feature = "memmap")]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.0 {
+            Some(ref x) => x,
+            None => &[],
+        }
+    }
+}
+
+/// A file that adds the number of bytes read from it to a shared counter, returned by
+/// `FileEntry::open_counting`.
+pub struct CountingFile {
+    file: File,
+    counter: Arc<AtomicU64>,
+}
+
+impl CountingFile {
+    /// The underlying file.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Stop counting and return the underlying file.
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
+
+impl Read for CountingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.counter.fetch_add(n as u64, AtomicOrdering::Relaxed);
+        Ok(n)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        let n = self.file.read_vectored(bufs)?;
+        self.counter.fetch_add(n as u64, AtomicOrdering::Relaxed);
+        Ok(n)
+    }
+}
+
 /// A value returned by the `Walk` iterator. Represents a file, socket, or anything that is not a
-/// directory.
-pub struct FileEntry<D = WithoutOpen, P = WithoutPath> {
+/// directory, or a directory if `Walk::include_dirs` is used.
+pub struct FileEntry<D = WithoutOpen, P = WithoutPath, E = WithoutDirent> {
     parent_node: Option<P>,
-    parent_dir: Option<D>,
-    segment: OsString,
+    open: D,
+    dirent: Option<E>,
+    segment: Segment,
+    kind: Kind,
+    /// 0 for the root of the walk, which may have any number of path nodes.
+    depth: u32,
+}
+
+/// A `FileEntry` that can be opened and knows its path, the same as calling `with_open` and
+/// `with_paths` on the walk.
+///
+/// ```rust
+/// use fdwalk::fileentry::FileNode;
+///
+/// for entry in fdwalk::walk_with::<FileNode, _>("src") {
+///     let entry = entry.unwrap();
+///     println!("{} {}", entry.to_path().display(), entry.stat().unwrap().st_size);
+/// }
+/// ```
+pub type FileNode = FileEntry<WithOpen, WithPath>;
+
+/// What the walk found out about an entry by the time it was yielded.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Unknown,
+    Directory,
+    UnreadableDirectory,
+    Symlink,
 }
 
-impl<P: PathProvider> FileEntry<WithOpen, P> {
+impl<D: OpenProvider, P: PathProvider, E: DirentProvider> FileEntry<D, P, E> {
+    /// The file name as raw bytes. For the root of the walk, this is whatever `walk` was called
+    /// with.
+    pub fn name_bytes(&self) -> &[u8] {
+        self.segment.as_bytes()
+    }
+
+    /// The file name without its extension, like `Path::file_stem`: `.bashrc` has no extension,
+    /// `a.tar.gz` has the stem `a.tar`. For roots like `.` or `/` that have no stem, this is the
+    /// entire name.
+    pub fn file_stem(&self) -> &OsStr {
+        let name = self.segment.as_os_str();
+        Path::new(name).file_stem().unwrap_or(name)
+    }
+
+    /// The extension of the file name, like `Path::extension`.
+    pub fn extension(&self) -> Option<&OsStr> {
+        Path::new(self.segment.as_os_str()).extension()
+    }
+
+    /// The file type (only the `S_IFMT` part), as far as known without a `stat()`.
+    ///
+    /// Directories and symlinks yielded by the walk are always known. For anything else this needs
+    /// `Walk::with_dirent`, and even then the filesystem may not report a type.
+    pub fn file_type(&self) -> Option<SFlag> {
+        match self.kind {
+            Kind::Directory | Kind::UnreadableDirectory => return Some(SFlag::S_IFDIR),
+            Kind::Symlink => return Some(SFlag::S_IFLNK),
+            Kind::Unknown => (),
+        }
+
+        let rv = match self.dirent.as_ref()?.file_type()? {
+            Type::Fifo => SFlag::S_IFIFO,
+            Type::CharacterDevice => SFlag::S_IFCHR,
+            Type::Directory => SFlag::S_IFDIR,
+            Type::BlockDevice => SFlag::S_IFBLK,
+            Type::File => SFlag::S_IFREG,
+            Type::Symlink => SFlag::S_IFLNK,
+            Type::Socket => SFlag::S_IFSOCK,
+        };
+        Some(rv)
+    }
+
+    /// Whether the walk yielded this entry as a directory it is about to descend into, see
+    /// `Walk::include_dirs`. Unlike checking `stat()`, this needs no syscall.
+    pub fn is_directory_entry(&self) -> bool {
+        self.kind == Kind::Directory
+    }
+
+    /// Whether this entry could be opened. This is only ever false for directories yielded by
+    /// `Walk::report_unreadable_dirs`.
+    pub fn is_readable(&self) -> bool {
+        self.kind != Kind::UnreadableDirectory
+    }
+
+    /// 0 for the root of the walk.
+    pub(crate) fn depth(&self) -> usize {
+        self.depth as usize
+    }
+}
+
+impl<P: PathProvider, E: DirentProvider> FileEntry<WithOpen, P, E> {
+    /// The fd `segment` is relative to. That is the current directory for the root of the walk.
+    fn dir_fd(&self) -> RawFd {
+        match self.open.parent_dir {
+            Some(ref x) => x.as_raw_fd(),
+            None => libc::AT_FDCWD,
+        }
+    }
+
     /// call `stat()` for this file.
+    ///
+    /// The result is cached, calling this again returns the same data even if the file has
+    /// changed in the meantime. Errors are not cached.
+    ///
+    /// For directories yielded by `Walk::include_dirs`, this uses `fstat` on the already open
+    /// directory. When following symlinks, that means you get the data of the directory a symlink
+    /// points to, not of the symlink itself.
     pub fn stat(&self) -> Result<FileStat, Errno> {
-        fstatat(
-            self.parent_dir.as_ref().unwrap().0.as_raw_fd(),
-            self.segment.as_os_str(),
-            AtFlags::AT_SYMLINK_NOFOLLOW,
-        )
+        if let Some(stat) = self.open.stat.get() {
+            return Ok(*stat);
+        }
+
+        let stat = match self.open.own_dir {
+            // directories that have been descended into can be asked directly
+            Some(ref dir) => fstat(dir.as_raw_fd())?,
+            None => fstatat(
+                self.dir_fd(),
+                self.segment.as_os_str(),
+                AtFlags::AT_SYMLINK_NOFOLLOW,
+            )?,
+        };
+        let _ = self.open.stat.set(stat);
+        Ok(stat)
+    }
+
+    /// The last modification time, `st_mtime` from `stat()`.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn modified(&self) -> Result<SystemTime, Errno> {
+        let stat = self.stat()?;
+        Ok(system_time(stat.st_mtime as i64, stat.st_mtime_nsec as i64))
+    }
+
+    /// The last access time, `st_atime` from `stat()`. Many filesystems are mounted with
+    /// `relatime` or `noatime`, so this is often not updated on every read.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn accessed(&self) -> Result<SystemTime, Errno> {
+        let stat = self.stat()?;
+        Ok(system_time(stat.st_atime as i64, stat.st_atime_nsec as i64))
+    }
+
+    /// The last time the inode changed, `st_ctime` from `stat()`. Unlike `modified()`, this is
+    /// also updated by changes to permissions, owner or link count, and can't be set by
+    /// `touch`.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn changed(&self) -> Result<SystemTime, Errno> {
+        let stat = self.stat()?;
+        Ok(system_time(stat.st_ctime as i64, stat.st_ctime_nsec as i64))
+    }
+
+    /// The file type (only the `S_IFMT` part), using `file_type()` if possible and `stat()`
+    /// otherwise, e.g. on filesystems that report `DT_UNKNOWN`.
+    ///
+    /// Like `stat()`, this does not follow symlinks.
+    pub fn file_type_or_stat(&self) -> Result<SFlag, Errno> {
+        match self.file_type() {
+            Some(x) => Ok(x),
+            None => Ok(SFlag::from_bits_truncate(self.stat()?.st_mode) & SFlag::S_IFMT),
+        }
+    }
+
+    /// The type (only the `S_IFMT` part) of the file a symlink points to, or of the file itself
+    /// if it is not a symlink.
+    ///
+    /// Fails with `ENOENT` for broken symlinks. Unlike `stat()`, this is not cached.
+    pub fn target_type(&self) -> Result<SFlag, Errno> {
+        let stat = fstatat(self.dir_fd(), self.segment.as_os_str(), AtFlags::empty())?;
+        Ok(SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT)
+    }
+
+    /// The `(st_dev, st_ino)` pair that identifies this file, from `stat()`.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn file_id(&self) -> Result<(u64, u64), Errno> {
+        let stat = self.stat()?;
+        Ok((stat.st_dev as u64, stat.st_ino as u64))
+    }
+
+    /// `file_id()`, or `None` if it failed. Unlike `file_id()` the error is cached as well, so
+    /// that an entry doesn't change its identity when `stat()` starts or stops failing.
+    fn cached_file_id(&self) -> Option<(u64, u64)> {
+        *self.open.id.get_or_init(|| self.file_id().ok())
+    }
+
+    /// Whether this directory is on a different device than its parent directory, i.e. whether
+    /// something is mounted here.
+    ///
+    /// Bind mounts within the same filesystem can't be detected this way. Fails with `ENOTDIR`
+    /// for the root of the walk if it is not a directory.
+    pub fn is_mount_point(&self) -> Result<bool, Errno> {
+        let parent = if self.depth > 0 {
+            fstat(self.dir_fd())?
+        } else {
+            // the root of the walk, there is no parent fd
+            let mut dotdot = self.segment.to_os_string();
+            dotdot.push("/..");
+            fstatat(self.dir_fd(), dotdot.as_os_str(), AtFlags::empty())?
+        };
+
+        Ok(self.stat()?.st_dev != parent.st_dev)
+    }
+
+    /// Whether the filesystem this entry is on is mounted read-only, e.g. to not even try to
+    /// delete anything there.
+    pub fn is_read_only_fs(&self) -> Result<bool, Errno> {
+        Ok(self
+            .filesystem_stats()?
+            .flags()
+            .contains(FsFlags::ST_RDONLY))
+    }
+
+    /// Information about the filesystem this entry is on, like free space, from `fstatvfs`.
+    ///
+    /// For directory entries this asks the directory itself, so that mount points report the
+    /// mounted filesystem, and the parent directory otherwise. Only the root of a walk started
+    /// from a path may need to be looked up by path.
+    ///
+    /// ```rust
+    /// let root = fdwalk::walk(".").with_open().include_dirs().next().unwrap().unwrap();
+    /// let stats = root.filesystem_stats().unwrap();
+    /// let free = stats.blocks_available() as u64 * stats.fragment_size() as u64;
+    /// println!("{} bytes free", free);
+    /// ```
+    pub fn filesystem_stats(&self) -> Result<Statvfs, Errno> {
+        match (&self.open.own_dir, &self.open.parent_dir) {
+            (Some(dir), _) | (None, Some(dir)) => fstatvfs(&***dir),
+            // the root of a walk started from a path
+            (None, None) => statvfs(self.segment.as_os_str()),
+        }
+    }
+
+    /// Start a new, independently configured walk rooted at this directory.
+    ///
+    /// For directory entries, this reuses the directory the walk already opened, otherwise it is
+    /// opened again. Paths in the new walk are relative to this directory.
+    pub fn walk(&self) -> Result<Walk, Errno> {
+        let dir = match self.open.own_dir {
+            Some(ref x) => Arc::clone(x),
+            #[allow(clippy::arc_with_non_send_sync)]
+            None => Arc::new(Dir::openat(
+                self.dir_fd(),
+                self.segment.as_os_str(),
+                OFlag::O_DIRECTORY,
+                Mode::empty(),
+            )?),
+        };
+        Ok(walk_fd(dir))
     }
 
     /// Open the file for reading.
@@ -101,49 +613,468 @@ impl<P: PathProvider> FileEntry<WithOpen, P> {
         self.open_options(OFlag::empty(), Mode::empty())
     }
 
+    /// Open the file for reading with `O_NONBLOCK`, so that opening a FIFO does not wait for a
+    /// writer, and neither does opening a device that waits for e.g. a carrier signal.
+    ///
+    /// The returned `File` stays in non-blocking mode: reads from FIFOs and devices that have no
+    /// data yet fail with `EAGAIN` (`io::ErrorKind::WouldBlock`) instead of waiting, and a FIFO
+    /// without a writer reads as empty. Regular files are not affected.
+    pub fn open_nonblock(&self) -> Result<File, Errno> {
+        self.open_options(OFlag::O_NONBLOCK, Mode::empty())
+    }
+
+    /// Open the file for reading, adding the number of bytes read to `counter`.
+    ///
+    /// Share one counter between all files for the throughput of a whole walk:
+    ///
+    /// ```rust,no_run
+    /// use std::io;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let total = Arc::new(AtomicU64::new(0));
+    /// for entry in fdwalk::walk(".").with_open() {
+    ///     let mut file = entry.unwrap().open_counting(total.clone()).unwrap();
+    ///     io::copy(&mut file, &mut io::sink()).unwrap();
+    /// }
+    /// println!("read {} bytes", total.load(Ordering::Relaxed));
+    /// ```
+    pub fn open_counting(&self, counter: Arc<AtomicU64>) -> Result<CountingFile, Errno> {
+        Ok(CountingFile {
+            file: self.open()?,
+            counter,
+        })
+    }
+
     /// Open the file with custom flags and open mode.
     pub fn open_options(&self, oflag: OFlag, mode: Mode) -> Result<File, Errno> {
-        let fd = openat(
-            self.parent_dir.as_ref().unwrap().0.as_raw_fd(),
+        let fd = openat(self.dir_fd(), self.segment.as_os_str(), oflag, mode);
+        fd.map(|x| unsafe { File::from_raw_fd(x) })
+    }
+
+    /// Open the directory containing this entry, e.g. to `sync_all()` it after creating or
+    /// renaming files in it.
+    ///
+    /// The returned `File` owns a duplicate of the fd the walk uses, so it can be closed
+    /// independently. For the root of a walk, the parent is opened by path.
+    pub fn open_parent_dir(&self) -> Result<File, Errno> {
+        let fd = match self.open.parent_dir {
+            Some(ref dir) if self.dirent.is_some() => {
+                fcntl(dir.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(0))?
+            }
+            _ => {
+                let path = Path::new(self.segment.as_os_str());
+                // `..` does not work if the root is not a directory
+                let parent = match path.components().next_back() {
+                    Some(Component::Normal(_)) => match path.parent() {
+                        Some(x) if x.as_os_str().is_empty() => Path::new(".").to_owned(),
+                        Some(x) => x.to_owned(),
+                        None => path.join(".."),
+                    },
+                    _ => path.join(".."),
+                };
+                openat(
+                    self.dir_fd(),
+                    parent.as_os_str(),
+                    OFlag::O_DIRECTORY | OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+                    Mode::empty(),
+                )?
+            }
+        };
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Change the permission bits with `fchmodat`.
+    ///
+    /// Symlinks are followed, Linux cannot change the mode of a symlink itself. A cached `stat()`
+    /// result is not updated.
+    pub fn set_permissions(&self, mode: Mode) -> Result<(), Errno> {
+        fchmodat(
+            Some(self.dir_fd()),
             self.segment.as_os_str(),
-            oflag,
             mode,
-        );
-        fd.map(|x| unsafe { File::from_raw_fd(x) })
+            FchmodatFlags::FollowSymlink,
+        )
+    }
+
+    /// Change the owner and/or group with `fchownat`, leaving out what is `None`.
+    ///
+    /// Symlinks are not followed. A cached `stat()` result is not updated.
+    pub fn set_owner(&self, uid: Option<Uid>, gid: Option<Gid>) -> Result<(), Errno> {
+        fchownat(
+            Some(self.dir_fd()),
+            self.segment.as_os_str(),
+            uid,
+            gid,
+            FchownatFlags::NoFollowSymlink,
+        )
+    }
+
+    /// Configure how to open the file, like `std::fs::OpenOptions`.
+    ///
+    /// ```rust,no_run
+    /// for entry in fdwalk::walk(".").with_open() {
+    ///     let file = entry.unwrap().open_with().append(true).open();
+    /// }
+    /// ```
+    pub fn open_with(&self) -> EntryOpenOptions<'_, P, E> {
+        EntryOpenOptions {
+            entry: self,
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: None,
+            nonblocking: false,
+        }
+    }
+
+    /// Read the entire file into a string.
+    pub fn read_to_string(&self) -> io::Result<String> {
+        let mut rv = String::new();
+        self.open()?.read_to_string(&mut rv)?;
+        Ok(rv)
+    }
+
+    /// Read the entire file into a byte vector.
+    pub fn read_to_end(&self) -> io::Result<Vec<u8>> {
+        let mut rv = Vec::new();
+        self.open()?.read_to_end(&mut rv)?;
+        Ok(rv)
+    }
+
+    /// Feed what `Walk::tree_hash` hashes for this entry into `hasher`.
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn hash_into<H: Hasher>(
+        &self,
+        hasher: &mut H,
+        input: TreeHashInput,
+    ) -> Result<(), Errno> {
+        if input == TreeHashInput::Stat {
+            let stat = self.stat()?;
+            hasher.write_u32(stat.st_mode as u32);
+            hasher.write_u32(stat.st_uid);
+            hasher.write_u32(stat.st_gid);
+            hasher.write_u64(stat.st_size as u64);
+            hasher.write_i64(stat.st_mtime as i64);
+            hasher.write_i64(stat.st_mtime_nsec as i64);
+            return Ok(());
+        }
+
+        let file_type = self.file_type_or_stat()?;
+        hasher.write_u32(file_type.bits() as u32);
+        if file_type == SFlag::S_IFLNK {
+            let target = readlinkat(self.dir_fd(), self.segment.as_os_str())?;
+            hasher.write(target.as_bytes());
+        } else if file_type == SFlag::S_IFREG {
+            let len = self.write_contents(hasher)?;
+            hasher.write_u64(len);
+        }
+        Ok(())
+    }
+
+    /// Feed the contents of the file into `hasher`, returning its length.
+    fn write_contents<H: Hasher>(&self, hasher: &mut H) -> Result<u64, Errno> {
+        let mut file = self.open()?;
+        let mut buf = vec![0; 64 * 1024];
+        let mut len = 0;
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => return Ok(len),
+                Ok(n) => {
+                    hasher.write(&buf[..n]);
+                    len += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.raw_os_error().map_or(Errno::EIO, Errno::from_i32)),
+            }
+        }
+    }
+
+    /// Map the file into memory, read-only.
+    ///
+    /// As with any mmap, the contents change if the file is modified by someone else while
+    /// mapped, and truncating it may cause `SIGBUS`.
+    #[cfg(feature = "memmap")]
+    pub fn mmap(&self) -> Result<Mmap, Errno> {
+        let file = self.open()?;
+        if fstat(file.as_raw_fd())?.st_size == 0 {
+            return Ok(Mmap(None));
+        }
+
+        unsafe { memmap2::Mmap::map(&file) }
+            .map(|x| Mmap(Some(x)))
+            .map_err(|e| e.raw_os_error().map_or(Errno::EIO, Errno::from_i32))
+    }
+}
+
+/// The builder returned by `FileEntry::open_with`.
+///
+/// Files are always opened with `O_CLOEXEC`. Use `FileEntry::open_options` for anything not
+/// covered here.
+pub struct EntryOpenOptions<'a, P, E> {
+    entry: &'a FileEntry<WithOpen, P, E>,
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: Option<Mode>,
+    nonblocking: bool,
+}
+
+impl<'a, P: PathProvider, E: DirentProvider> EntryOpenOptions<'a, P, E> {
+    /// Open for reading.
+    pub fn read(mut self, read: bool) -> Self {
+        self.read = read;
+        self
+    }
+
+    /// Open for writing.
+    pub fn write(mut self, write: bool) -> Self {
+        self.write = write;
+        self
+    }
+
+    /// Open for appending, this implies `write`.
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Truncate the file to zero length. Needs `write`.
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Create the file with `mode` if it no longer exists.
+    pub fn create(mut self, mode: Mode) -> Self {
+        self.create = Some(mode);
+        self
+    }
+
+    /// Open with `O_NONBLOCK`, see `FileEntry::open_nonblock`.
+    pub fn nonblocking(mut self, nonblocking: bool) -> Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Open the file. Fails with `EINVAL` if neither reading nor writing was requested, or if
+    /// `truncate` was requested without writing.
+    pub fn open(self) -> Result<File, Errno> {
+        let write = self.write || self.append;
+        let mut oflag = match (self.read, write) {
+            (true, false) => OFlag::O_RDONLY,
+            (false, true) => OFlag::O_WRONLY,
+            (true, true) => OFlag::O_RDWR,
+            (false, false) => return Err(Errno::EINVAL),
+        };
+        if self.truncate && !write {
+            return Err(Errno::EINVAL);
+        }
+
+        oflag |= OFlag::O_CLOEXEC;
+        if self.append {
+            oflag |= OFlag::O_APPEND;
+        }
+        if self.truncate {
+            oflag |= OFlag::O_TRUNC;
+        }
+        if self.create.is_some() {
+            oflag |= OFlag::O_CREAT;
+        }
+        if self.nonblocking {
+            oflag |= OFlag::O_NONBLOCK;
+        }
+
+        self.entry
+            .open_options(oflag, self.create.unwrap_or_else(Mode::empty))
     }
 }
 
-impl<D: OpenProvider> FileEntry<D, WithPath> {
+impl<D: OpenProvider, E: DirentProvider> FileEntry<D, WithPath, E> {
     /// Return the file entry's path from a linked list kept in memory.
     ///
     /// This may return paths that exceed the size of paths that can be passed to syscalls.
     pub fn to_path(&self) -> PathBuf {
-        // XXX: slow, also self.segment apparently == self.parent_node.segment?
-        let mut segments = vec![];
-
-        let mut current_opt: Option<&WithPath> = self.parent_node.as_ref();
+        WithPath::to_path(self.parent_node.as_ref()).unwrap()
+    }
 
-        while let Some(ref mut current) = current_opt {
-            segments.push(&current.0.segment);
-            current_opt = current.0.parent.as_ref();
+    /// The path this entry would have if the walk had started at `new_base` instead, e.g. the
+    /// destination when copying a tree. The root itself is rebased onto `new_base`.
+    ///
+    /// ```rust
+    /// use std::path::Path;
+    ///
+    /// for entry in fdwalk::walk("src").with_paths() {
+    ///     let dest = entry.unwrap().rebase(Path::new("/tmp/backup"));
+    ///     assert!(dest.starts_with("/tmp/backup"));
+    /// }
+    /// ```
+    pub fn rebase(&self, new_base: &Path) -> PathBuf {
+        let mut segments = Vec::with_capacity(self.depth as usize);
+        let mut capacity = new_base.as_os_str().len();
+        let mut node = self.parent_node.as_ref();
+        for _ in 0..self.depth {
+            let current = node.unwrap();
+            segments.push(current.0.segment.as_os_str());
+            // one extra byte for the separator
+            capacity += current.0.segment.len() + 1;
+            node = current.0.parent.as_ref();
         }
 
-        let mut rv = PathBuf::new();
-
+        let mut rv = PathBuf::with_capacity(capacity);
+        rv.push(new_base);
         for segment in segments.into_iter().rev() {
             rv.push(segment);
         }
-
         rv
     }
+
+    /// The path of the directory containing this entry, the same as `ancestor(1)`.
+    pub fn parent_path(&self) -> Option<PathBuf> {
+        self.ancestor(1)
+    }
+
+    /// The path of the `n`-th directory above this entry, so `ancestor(0)` is the path of the
+    /// entry itself. This skips `n` nodes of the linked list instead of cutting down a full path.
+    ///
+    /// Relative paths end at `.`, so the parent of both `a` and `./a` is `.`. `None` is returned
+    /// when going past that, or past `/` or `..`.
+    pub fn ancestor(&self, n: usize) -> Option<PathBuf> {
+        let mut node = self.parent_node.as_ref();
+        for _ in 0..n {
+            let current = node?;
+            if matches!(current.0.segment.as_bytes(), b"/" | b"." | b"..") {
+                return None;
+            }
+            node = current.0.parent.as_ref();
+        }
+        WithPath::to_path(node)
+    }
+
+    /// The components of the path from the root of the walk to this entry, the same as
+    /// `to_path().components()` would return.
+    ///
+    /// The segments are borrowed from the entry, only a vector of references to them is
+    /// allocated, as the linked list is stored leaf first.
+    ///
+    /// ```rust
+    /// for entry in fdwalk::walk("src").with_paths() {
+    ///     let entry = entry.unwrap();
+    ///     let in_tests = entry.path_components().any(|x| x == "tests");
+    /// }
+    /// ```
+    pub fn path_components(&self) -> impl DoubleEndedIterator<Item = &OsStr> + ExactSizeIterator {
+        let mut components = Vec::new();
+        let mut current = self.parent_node.as_ref();
+        while let Some(node) = current {
+            components.push(node.0.segment.as_os_str());
+            current = node.0.parent.as_ref();
+        }
+
+        if components.is_empty() {
+            // the root of `walk(".")`
+            components.push(OsStr::new("."));
+        }
+        components.into_iter().rev()
+    }
+}
+
+impl<D: OpenProvider, E: DirentProvider> FileEntry<D, WithInternedPath, E> {
+    /// Return the file entry's path, see `FileEntry::to_path` for `WithPath`.
+    pub fn to_path(&self) -> PathBuf {
+        WithInternedPath::to_path(self.parent_node.as_ref()).unwrap()
+    }
 }
 
-impl<D: OpenProvider, P: PathProvider> Entry for FileEntry<D, P> {
-    fn root(segment: &OsStr) -> Self {
+/// Entries with paths compare equal if their paths are equal. Ordering is by the raw bytes of the
+/// path. Entries without paths have nothing that could be compared.
+///
+/// Every comparison builds both paths. To sort many entries, `sort_by_cached_key(|x|
+/// x.to_path())` is faster.
+impl<D: OpenProvider, E: DirentProvider> PartialEq for FileEntry<D, WithPath, E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_path() == other.to_path()
+    }
+}
+
+impl<D: OpenProvider, E: DirentProvider> Eq for FileEntry<D, WithPath, E> {}
+
+impl<D: OpenProvider, E: DirentProvider> PartialOrd for FileEntry<D, WithPath, E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<D: OpenProvider, E: DirentProvider> Ord for FileEntry<D, WithPath, E> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_path()
+            .as_os_str()
+            .as_bytes()
+            .cmp(other.to_path().as_os_str().as_bytes())
+    }
+}
+
+/// Entries that can be opened but don't have paths are identified by the file they point to, see
+/// `FileEntry::file_id`. Two entries for the same inode, e.g. hardlinks, compare equal.
+///
+/// The id is looked up the first time an entry is compared or hashed and then kept for the
+/// lifetime of the entry. Entries that could not be `stat()`ed at that point are only equal to
+/// themselves.
+impl<E: DirentProvider> PartialEq for FileEntry<WithOpen, WithoutPath, E> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.cached_file_id(), other.cached_file_id()) {
+            (Some(a), Some(b)) => a == b,
+            _ => ptr::eq(self, other),
+        }
+    }
+}
+
+impl<E: DirentProvider> Eq for FileEntry<WithOpen, WithoutPath, E> {}
+
+impl<E: DirentProvider> Hash for FileEntry<WithOpen, WithoutPath, E> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if let Some(id) = self.cached_file_id() {
+            id.hash(state);
+        }
+    }
+}
+
+impl<D: OpenProvider, P: PathProvider> FileEntry<D, P, WithDirent> {
+    /// Return the directory entry this file was found with, as returned by `getdents`.
+    ///
+    /// This is `None` for the root of the walk, which was not found in any directory.
+    pub fn dirent(&self) -> Option<&Dirent> {
+        self.dirent.as_ref().map(|x| &x.0)
+    }
+
+    /// The inode number `getdents` reported for this file, without a `stat()`. Together with the
+    /// `st_dev` of the directory, this identifies the file, e.g. to find hardlinks.
+    ///
+    /// This is `None` for the root of the walk, and if the filesystem reported 0. Some
+    /// filesystems report numbers that don't match `st_ino`: on a mount point, `d_ino` is that of
+    /// the directory underneath, overlayfs may report the inode of a lower layer, and some FUSE
+    /// filesystems make them up. Use `FileEntry::file_id` where that matters.
+    pub fn dir_ino(&self) -> Option<u64> {
+        self.dirent().map(Dirent::ino).filter(|&ino| ino != 0)
+    }
+}
+
+impl<D: OpenProvider, P: PathProvider, E: DirentProvider> Entry for FileEntry<D, P, E> {
+    fn root(parent_dir: Option<&Arc<Dir>>, segment: &OsStr) -> Self {
+        // The root may consist of many path components. Give each of them its own node, so that
+        // the path looks the same as if the walk had descended into it.
+        //
+        // A root of exactly `.` gets no node at all, so that `walk(".")` yields `foo` instead of
+        // `./foo`. `walk("./a")` on the other hand keeps its prefix.
         FileEntry {
-            parent_node: Some(P::path_provider(None, segment)),
-            parent_dir: None,
-            segment: segment.to_owned(),
+            parent_node: root_path_node(Path::new(segment)),
+            open: D::dir_provider(parent_dir),
+            dirent: None,
+            segment: segment.into(),
+            kind: Kind::Unknown,
+            depth: 0,
         }
     }
 
@@ -151,11 +1082,65 @@ impl<D: OpenProvider, P: PathProvider> Entry for FileEntry<D, P> {
         &self.segment
     }
 
-    fn new_child(&self, parent_dir: &Arc<Dir>, segment: &OsStr) -> Self {
+    fn path(&self) -> Option<PathBuf> {
+        P::to_path(self.parent_node.as_ref())
+    }
+
+    fn stat(&self) -> Option<Result<FileStat, Errno>> {
+        D::stat_entry(self)
+    }
+
+    fn new_child(&self, parent_dir: &Arc<Dir>, segment: &OsStr, dirent: &Dirent) -> Self {
         FileEntry {
-            parent_dir: Some(D::dir_provider(parent_dir.clone())),
+            open: D::dir_provider(Some(parent_dir)),
             parent_node: Some(P::path_provider(self.parent_node.as_ref(), segment)),
-            segment: segment.to_owned(),
+            dirent: Some(E::dirent_provider(dirent)),
+            segment: segment.into(),
+            kind: Kind::Unknown,
+            // `u32` keeps entries small, and no tree is that deep, but `depth_limit` may be off
+            depth: self.depth.saturating_add(1),
+        }
+    }
+
+    fn relabel_root(&mut self, path: &Path) {
+        self.parent_node = root_path_node(path);
+    }
+
+    fn mark_directory(&mut self, dir: Option<&Arc<Dir>>) {
+        match dir {
+            Some(dir) => {
+                self.kind = Kind::Directory;
+                self.open.set_own_dir(dir);
+            }
+            None => self.kind = Kind::UnreadableDirectory,
+        }
+    }
+
+    fn mark_symlink(&mut self) {
+        self.kind = Kind::Symlink;
+    }
+}
+
+/// Turn a timestamp from `stat()` into a `SystemTime`.
+pub(crate) fn system_time(secs: i64, nsecs: i64) -> SystemTime {
+    let nsecs = Duration::from_nanos(nsecs as u64);
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64) + nsecs
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + nsecs
+    }
+}
+
+/// Build the path nodes for the root of a walk, see `Entry::root`.
+fn root_path_node<P: PathProvider>(path: &Path) -> Option<P> {
+    let mut parent_node = None;
+    if path.as_os_str() != "." {
+        for component in path.components() {
+            parent_node = Some(P::path_provider(
+                parent_node.as_ref(),
+                component.as_os_str(),
+            ));
         }
     }
+    parent_node
 }
@@ -0,0 +1,260 @@
+use std::error::Error;
+use std::fmt;
+
+/// An invalid pattern passed to `Walk::include_glob`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobError {
+    pattern: String,
+    reason: &'static str,
+}
+
+impl GlobError {
+    fn new(pattern: &str, reason: &'static str) -> Self {
+        GlobError {
+            pattern: pattern.to_owned(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for GlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid glob {:?}: {}", self.pattern, self.reason)
+    }
+}
+
+impl Error for GlobError {}
+
+/// A pattern matched against paths relative to the root of the walk, split at `/`.
+#[derive(Debug, Clone)]
+pub(crate) struct Glob {
+    components: Vec<Component>,
+}
+
+#[derive(Debug, Clone)]
+enum Component {
+    /// `**`, any number of path components.
+    AnyDirs,
+    Name(Vec<Token>),
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Byte(u8),
+    /// `?`
+    AnyByte,
+    /// `*`
+    AnyBytes,
+    /// `[a-z]` or `[!a-z]`
+    Class {
+        negated: bool,
+        ranges: Vec<(u8, u8)>,
+    },
+}
+
+impl Glob {
+    pub(crate) fn new(pattern: &str) -> Result<Self, GlobError> {
+        let mut components = vec![];
+
+        for part in pattern.as_bytes().split(|&x| x == b'/') {
+            match part {
+                b"" => continue,
+                b"**" => components.push(Component::AnyDirs),
+                b"." | b".." => {
+                    return Err(GlobError::new(pattern, "`.` and `..` are not supported"))
+                }
+                _ => components.push(Component::Name(parse_name(pattern, part)?)),
+            }
+        }
+
+        if components.is_empty() {
+            return Err(GlobError::new(pattern, "empty pattern"));
+        }
+
+        Ok(Glob { components })
+    }
+}
+
+fn parse_name(pattern: &str, part: &[u8]) -> Result<Vec<Token>, GlobError> {
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < part.len() {
+        let token = match part[i] {
+            b'?' => Token::AnyByte,
+            b'*' => Token::AnyBytes,
+            b'\\' => {
+                i += 1;
+                match part.get(i) {
+                    Some(&x) => Token::Byte(x),
+                    None => return Err(GlobError::new(pattern, "trailing backslash")),
+                }
+            }
+            b'[' => {
+                i += 1;
+                let negated = matches!(part.get(i), Some(b'!') | Some(b'^'));
+                if negated {
+                    i += 1;
+                }
+
+                let mut ranges = vec![];
+                // a `]` right at the start is part of the class
+                let start = i;
+                loop {
+                    let lo = match part.get(i) {
+                        Some(b']') if i > start => break,
+                        Some(&x) => x,
+                        None => return Err(GlobError::new(pattern, "unclosed `[`")),
+                    };
+                    match (part.get(i + 1), part.get(i + 2)) {
+                        (Some(b'-'), Some(&hi)) if hi != b']' => {
+                            ranges.push((lo, hi));
+                            i += 3;
+                        }
+                        _ => {
+                            ranges.push((lo, lo));
+                            i += 1;
+                        }
+                    }
+                }
+
+                Token::Class { negated, ranges }
+            }
+            x => Token::Byte(x),
+        };
+        tokens.push(token);
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+fn byte_eq(a: u8, b: u8, case_insensitive: bool) -> bool {
+    if case_insensitive {
+        a.eq_ignore_ascii_case(&b)
+    } else {
+        a == b
+    }
+}
+
+fn match_byte(token: &Token, byte: u8, case_insensitive: bool) -> bool {
+    match *token {
+        Token::Byte(x) => byte_eq(x, byte, case_insensitive),
+        Token::AnyByte => true,
+        Token::Class {
+            negated,
+            ref ranges,
+        } => {
+            let lower = byte.to_ascii_lowercase();
+            let upper = byte.to_ascii_uppercase();
+            let in_class = ranges.iter().any(|&(lo, hi)| {
+                (lo..=hi).contains(&byte)
+                    || case_insensitive
+                        && ((lo..=hi).contains(&lower) || (lo..=hi).contains(&upper))
+            });
+            in_class != negated
+        }
+        Token::AnyBytes => false,
+    }
+}
+
+fn match_name(tokens: &[Token], name: &[u8], case_insensitive: bool) -> bool {
+    let mut t = 0;
+    let mut n = 0;
+    // the token after the last `*` seen, and where in `name` that `*` stopped matching. Only the
+    // last `*` ever needs to be retried: if what follows it can't match anywhere further on, no
+    // earlier `*` swallowing more would help. That makes this O(tokens * name) rather than
+    // exponential in the number of `*`s.
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match tokens.get(t) {
+            Some(Token::AnyBytes) => {
+                t += 1;
+                star = Some((t, n));
+            }
+            Some(token) if match_byte(token, name[n], case_insensitive) => {
+                t += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_t, star_n)) => {
+                    // let the `*` swallow one more byte and retry
+                    t = star_t;
+                    n = star_n + 1;
+                    star = Some((star_t, n));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    tokens[t..].iter().all(|x| matches!(x, Token::AnyBytes))
+}
+
+/// The positions in a set of globs that the path walked so far could be at, as pairs of index of
+/// the glob and index of the next component to match.
+///
+/// Entries with an empty state can't match, and neither can anything below them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GlobState(Vec<(usize, usize)>);
+
+impl GlobState {
+    /// The state for the root of the walk.
+    pub(crate) fn start(globs: &[Glob]) -> Self {
+        let mut state = GlobState::default();
+        for i in 0..globs.len() {
+            state.insert(globs, i, 0);
+        }
+        state
+    }
+
+    /// Add a position, and the positions after any `**` at it, since those may match nothing.
+    fn insert(&mut self, globs: &[Glob], glob: usize, mut pos: usize) {
+        loop {
+            if !self.0.contains(&(glob, pos)) {
+                self.0.push((glob, pos));
+            }
+            match globs[glob].components.get(pos) {
+                Some(Component::AnyDirs) => pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    /// The state for a child entry called `name`.
+    pub(crate) fn advance(&self, globs: &[Glob], name: &[u8], case_insensitive: bool) -> Self {
+        let mut state = GlobState::default();
+        for &(glob, pos) in &self.0 {
+            match globs[glob].components.get(pos) {
+                Some(Component::AnyDirs) => state.insert(globs, glob, pos),
+                Some(Component::Name(tokens)) if match_name(tokens, name, case_insensitive) => {
+                    state.insert(globs, glob, pos + 1)
+                }
+                _ => (),
+            }
+        }
+        state
+    }
+
+    /// Whether nothing at or below this entry can match.
+    pub(crate) fn is_dead(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether nothing below this entry can match, even though it may match itself.
+    pub(crate) fn is_exhausted(&self, globs: &[Glob]) -> bool {
+        !self.0.is_empty()
+            && self
+                .0
+                .iter()
+                .all(|&(glob, pos)| pos == globs[glob].components.len())
+    }
+
+    /// Whether the path walked so far matches a glob.
+    pub(crate) fn is_match(&self, globs: &[Glob]) -> bool {
+        self.0
+            .iter()
+            .any(|&(glob, pos)| pos == globs[glob].components.len())
+    }
+}
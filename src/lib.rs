@@ -1,167 +1,2128 @@
 #![deny(missing_docs)]
 //! TODO
+mod borrowed;
+mod counted;
 pub mod fileentry;
+mod glob;
+mod parallel;
+mod segment;
+mod visit;
 
-use fileentry::{OpenProvider, FileEntry, PathProvider, WithOpen, WithPath, WithoutOpen, WithoutPath};
+use fileentry::{
+    system_time, DirentProvider, FileEntry, OpenProvider, PathProvider, WithDirent,
+    WithInternedPath, WithOpen, WithPath, WithoutDirent, WithoutOpen, WithoutPath,
+};
 
-use nix::dir::Dir;
+use borrowed::ScratchPath;
+pub use borrowed::{BorrowedEntry, BorrowedPaths};
+pub use counted::CountOnly;
+use counted::Counted;
+pub use glob::GlobError;
+use glob::{Glob, GlobState};
+pub use parallel::ParallelWalk;
+use parallel::{FdBudget, FdPermit};
+pub use visit::{DirDecision, FilterDecision, Visitor};
+
+use nix::dir::{Dir, Type};
 use nix::errno::Errno;
-use nix::fcntl::OFlag;
-use nix::sys::stat::Mode;
+use nix::fcntl::{openat, AtFlags, OFlag};
+use nix::libc;
+use nix::sys::stat::{fstat, fstatat, FileStat, Mode, SFlag};
+use nix::unistd::{close, Gid, Uid};
+use std::cmp::{self, Reverse};
+use std::collections::{BinaryHeap, HashSet, VecDeque};
 use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::hash::Hasher;
 use std::mem::ManuallyDrop;
+use std::ops::Deref;
 use std::os::unix::ffi::OsStrExt;
-use std::os::unix::io::AsRawFd;
-use std::path::Path;
-use std::sync::Arc;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+/// The interesting parts of a raw directory entry, as returned by `getdents`.
+#[derive(Debug, Clone, Copy)]
+pub struct Dirent {
+    ino: u64,
+    file_type: Option<Type>,
+}
+
+impl Dirent {
+    fn new(entry: &nix::dir::Entry) -> Self {
+        Dirent {
+            ino: entry.ino(),
+            file_type: entry.file_type(),
+        }
+    }
+
+    /// The inode number (`d_ino`). Not every filesystem fills this in reliably.
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// The file type (`d_type`), or `None` if the filesystem did not report it.
+    pub fn file_type(&self) -> Option<Type> {
+        self.file_type
+    }
+}
+
+/// An error that stopped the walk from making progress at some point.
+///
+/// Walking continues after an error, so the next call to `next()` may well return more entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WalkError {
+    /// A syscall failed.
+    Sys(Errno),
+    /// A directory had more entries than `Walk::max_entries_per_dir` allows. Contains the path of
+    /// the directory if paths are tracked, and its name otherwise.
+    DirTruncated(PathBuf),
+    /// The root of the walk is a symlink, see `Walk::reject_symlink_root`.
+    SymlinkRoot(PathBuf),
+    /// A directory changed while it was read, or an entry vanished before it could be looked at,
+    /// see `Walk::detect_mutation`. Contains the path if paths are tracked, and the name
+    /// otherwise.
+    TreeChanged(PathBuf),
+    /// The kernel does not support `openat2`, which `walk_sandboxed` requires.
+    SandboxUnsupported,
+    /// A directory was not read because it is nested deeper than `Walk::depth_limit` allows.
+    /// Contains the path if paths are tracked, and the name otherwise.
+    MaxDepthExceeded(PathBuf),
+}
+
+impl WalkError {
+    /// The errno of the failing syscall, if any.
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            WalkError::Sys(errno) => Some(errno),
+            WalkError::SandboxUnsupported => Some(Errno::ENOSYS),
+            WalkError::DirTruncated(_)
+            | WalkError::SymlinkRoot(_)
+            | WalkError::TreeChanged(_)
+            | WalkError::MaxDepthExceeded(_) => None,
+        }
+    }
+}
+
+impl From<Errno> for WalkError {
+    fn from(errno: Errno) -> Self {
+        WalkError::Sys(errno)
+    }
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            WalkError::Sys(errno) => errno.fmt(f),
+            WalkError::DirTruncated(ref path) => {
+                write!(
+                    f,
+                    "too many entries in {}, skipped the rest",
+                    path.display()
+                )
+            }
+            WalkError::SymlinkRoot(ref path) => {
+                write!(f, "refusing to walk {}, it is a symlink", path.display())
+            }
+            WalkError::TreeChanged(ref path) => {
+                write!(f, "{} changed during the walk", path.display())
+            }
+            WalkError::SandboxUnsupported => {
+                write!(
+                    f,
+                    "cannot walk sandboxed, the kernel does not support openat2"
+                )
+            }
+            WalkError::MaxDepthExceeded(ref path) => {
+                write!(f, "{} is nested too deeply, skipped it", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            WalkError::Sys(ref errno) => Some(errno),
+            WalkError::DirTruncated(_)
+            | WalkError::SymlinkRoot(_)
+            | WalkError::TreeChanged(_)
+            | WalkError::SandboxUnsupported
+            | WalkError::MaxDepthExceeded(_) => None,
+        }
+    }
+}
+
+/// The result of `Walk::count_files`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalkCounts {
+    /// Regular files.
+    pub files: u64,
+    /// Directories, only counted if they are yielded, e.g. with `include_dirs`.
+    pub dirs: u64,
+    /// Symlinks that are yielded as such, i.e. not followed.
+    pub symlinks: u64,
+    /// Everything else: devices, sockets, FIFOs.
+    pub other: u64,
+}
+
+impl WalkCounts {
+    fn add(&mut self, file_type: Type) {
+        match file_type {
+            Type::File => self.files += 1,
+            Type::Directory => self.dirs += 1,
+            Type::Symlink => self.symlinks += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn file_id(stat: &FileStat) -> (u64, u64) {
+    (stat.st_dev as u64, stat.st_ino as u64)
+}
+
+fn stat_type(stat: &FileStat) -> Type {
+    match SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT {
+        SFlag::S_IFDIR => Type::Directory,
+        SFlag::S_IFREG => Type::File,
+        SFlag::S_IFLNK => Type::Symlink,
+        SFlag::S_IFIFO => Type::Fifo,
+        SFlag::S_IFCHR => Type::CharacterDevice,
+        SFlag::S_IFBLK => Type::BlockDevice,
+        _ => Type::Socket,
+    }
+}
+
+/// What `Walk::tree_hash` hashes for each entry, besides its path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeHashInput {
+    /// The type of each entry, the contents of files and the targets of symlinks.
+    Contents,
+    /// The `stat()` data of each entry: type, permissions, owner, size and modification time.
+    /// Much cheaper, but also changes when files are touched without being modified.
+    Stat,
+}
 
 /// A directory entry. You can potentially swap out the entire struct used to represent directory
 /// entries for your own instead of using `FileEntry`, and customize every aspect of what data is
 /// tracked while walking through directories.
 pub trait Entry {
     /// Construct a new "root" entry. This is called with potentially an entire directory path,
-    /// i.e. whatever `walk` was called with.
-    fn root(segment: &OsStr) -> Self;
+    /// i.e. whatever `walk` was called with, relative to `parent_dir` if given and the current
+    /// directory otherwise.
+    fn root(parent_dir: Option<&Arc<Dir>>, segment: &OsStr) -> Self;
+
+    /// Get the current path segment.
+    fn segment(&self) -> &OsStr;
+
+    /// Called on the root entry to change the path it is displayed as, without changing what is
+    /// opened. See `Walk::relative_to`.
+    fn relabel_root(&mut self, _path: &Path) {}
+
+    /// The full path of this entry, if it is tracked. Only used for error messages.
+    fn path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// `stat()` this entry, if it can. Used by filters on stat data, see `Walk::owned_by`.
+    /// Entries without this are never yielded when such filters are set.
+    fn stat(&self) -> Option<Result<FileStat, Errno>> {
+        None
+    }
+
+    /// Create a child entry based on current one.
+    fn new_child(&self, parent_dir: &Arc<Dir>, segment: &OsStr, dirent: &Dirent) -> Self;
+
+    /// Called before this entry is yielded as a directory, with the directory it has been opened
+    /// as. `dir` is `None` if the directory could not be opened, see
+    /// `Walk::report_unreadable_dirs`.
+    fn mark_directory(&mut self, _dir: Option<&Arc<Dir>>) {}
+
+    /// Called before this entry is yielded as a symlink that is not followed.
+    fn mark_symlink(&mut self) {}
+}
+
+/// See `Walk::depth_limit`.
+const DEFAULT_DEPTH_LIMIT: usize = 4096;
+
+/// Configuration of a `Walk` that is independent of the entry type, so it survives `with_entry`.
+#[derive(Clone, Default)]
+struct WalkOptions {
+    follow_symlinks: bool,
+    /// With `follow_symlinks`, don't follow symlinks to anything but directories.
+    dir_symlinks_only: bool,
+    readahead: usize,
+    /// Yield all files of a directory before descending into its subdirectories.
+    files_first: bool,
+    include_dirs: bool,
+    skip_files: bool,
+    cancel: Option<Arc<AtomicBool>>,
+    deadline: Option<Instant>,
+    name_filters: Vec<NameFilter>,
+    case_insensitive: bool,
+    report_unreadable_dirs: bool,
+    /// Yield symlinks instead of skipping them when not following them.
+    yield_symlinks: bool,
+    dir_open_flags: Option<OFlag>,
+    /// If not empty, only entries matching one of these are yielded.
+    globs: Vec<Glob>,
+    max_entries_per_dir: Option<usize>,
+    include_dot_entries: bool,
+    /// Skip everything that is not `S_IFREG`, see `Walk::regular_files_only`.
+    regular_files_only: bool,
+    /// Directories at this depth are yielded, but not read.
+    max_depth: Option<usize>,
+    /// Directories at this depth are an error, `DEFAULT_DEPTH_LIMIT` if not set.
+    depth_limit: Option<usize>,
+    report_truncated_dirs: bool,
+    reject_symlink_root: bool,
+    detect_mutation: bool,
+    /// Open directories with `openat2` so they can't be outside of the parent directory.
+    sandboxed: bool,
+    no_magiclinks: bool,
+    relative_to: Option<PathBuf>,
+    stat_filters: Vec<StatFilter>,
+    total_dirs: Option<u64>,
+    on_dir_enter: Option<DirHook>,
+    fd_budget: Option<Arc<FdBudget>>,
+}
+
+/// See `Walk::on_dir_enter_id`.
+type DirHook = Arc<Mutex<dyn FnMut(u64, u64, &Path) + Send>>;
+
+/// A filter on the raw bytes of an entry's name.
+#[derive(Clone)]
+enum NameFilter {
+    Prefix(Vec<u8>),
+    Suffix(Vec<u8>),
+}
+
+impl NameFilter {
+    fn matches(&self, name: &[u8], case_insensitive: bool) -> bool {
+        let (pattern, part) = match self {
+            NameFilter::Prefix(prefix) if name.len() >= prefix.len() => {
+                (prefix, &name[..prefix.len()])
+            }
+            NameFilter::Suffix(suffix) if name.len() >= suffix.len() => {
+                (suffix, &name[name.len() - suffix.len()..])
+            }
+            _ => return false,
+        };
+
+        if case_insensitive {
+            part.eq_ignore_ascii_case(pattern)
+        } else {
+            part == &pattern[..]
+        }
+    }
+}
+
+/// A filter on an entry's `stat()` data.
+#[derive(Clone)]
+enum StatFilter {
+    Uid(Uid),
+    Gid(Gid),
+    /// Mask and expected value.
+    Mode(libc::mode_t, libc::mode_t),
+    /// `(st_dev, st_ino)` pairs to skip.
+    SkipInodes(Arc<HashSet<(u64, u64)>>),
+    /// Oldest `st_ctime` that is not yielded.
+    ChangedAfter(SystemTime),
+}
+
+impl StatFilter {
+    fn matches(&self, stat: &FileStat) -> bool {
+        match *self {
+            StatFilter::Uid(uid) => stat.st_uid == uid.as_raw(),
+            StatFilter::Gid(gid) => stat.st_gid == gid.as_raw(),
+            StatFilter::Mode(mask, value) => stat.st_mode & mask == value,
+            StatFilter::SkipInodes(ref set) => !set.contains(&file_id(stat)),
+            #[allow(clippy::unnecessary_cast)]
+            StatFilter::ChangedAfter(t) => {
+                system_time(stat.st_ctime as i64, stat.st_ctime_nsec as i64) > t
+            }
+        }
+    }
+}
+
+/// A directory that the entries found in it keep open to `openat` relative to, possibly on
+/// other threads than the one that opened it.
+#[derive(Clone)]
+pub(crate) struct SharedDir(Arc<Dir>);
+
+impl SharedDir {
+    pub(crate) fn new(dir: Dir) -> Self {
+        // not `Send` without the impls below
+        #[allow(clippy::arc_with_non_send_sync)]
+        SharedDir(Arc::new(dir))
+    }
+}
+
+impl From<Arc<Dir>> for SharedDir {
+    fn from(dir: Arc<Dir>) -> Self {
+        SharedDir(dir)
+    }
+}
+
+impl Deref for SharedDir {
+    type Target = Arc<Dir>;
+
+    fn deref(&self) -> &Arc<Dir> {
+        &self.0
+    }
+}
+
+// SAFETY: `Dir` is `Send` but not `Sync` because `readdir` mutates the `DIR` it points to, which
+// takes `&mut Dir`. Behind a shared `Arc` nobody can get a `&mut Dir` anymore, not even through
+// `Arc::get_mut` while this clone exists, and the only `&self` method of `Dir` is `as_raw_fd`.
+// Reading directories happens through a separate `Dir::from_fd` on the thread that opened them.
+unsafe impl Send for SharedDir {}
+unsafe impl Sync for SharedDir {}
+
+/// An entry on the walk stack that has not been looked at yet.
+struct Pending<N> {
+    node: N,
+    parent_dir: Option<SharedDir>,
+    /// `d_type`, if known.
+    file_type: Option<Type>,
+    /// Whether `posix_fadvise` has already been issued for this entry.
+    advised: bool,
+    glob: GlobState,
+    /// 0 for the root of the walk.
+    depth: usize,
+    /// `.` or `..`, see `Walk::include_dot_entries`.
+    dot: bool,
+    /// The share of `ParallelWalk::fd_budget` taken by `parent_dir`.
+    permit: Option<Arc<FdPermit>>,
+    /// A non-directory that is pushed after the directories next to it, so that all files of a
+    /// directory are yielded before any of its subdirectories are read.
+    file: bool,
+}
+
+/// The iterator returned from `walk`. Use its methods to configure directory walking.
+///
+/// Since `Walk` is an iterator, so is `&mut Walk`. Iterate over a mutable reference to stop
+/// early and resume later:
+///
+/// ```rust
+/// let mut walk = fdwalk::walk(".");
+/// for entry in &mut walk {
+///     entry.unwrap();
+///     break;
+/// }
+/// let rest = walk.count();
+/// ```
+pub struct Walk<N: Entry = FileEntry> {
+    path: OsString,
+    root_dir: Option<SharedDir>,
+    options: WalkOptions,
+    walk_stack: Vec<Pending<N>>,
+    /// Scratch space for holding back the files of a directory, reused across directories.
+    files_buf: Vec<Pending<N>>,
+    /// Errors to return after the directory they belong to has been yielded, in order.
+    deferred_errors: VecDeque<WalkError>,
+    /// If the last entry yielded was a directory, the length of the walk stack before its
+    /// children were pushed.
+    last_dir: Option<usize>,
+    /// Directories opened so far, for `progress`.
+    visited_dirs: u64,
+    /// Entries excluded by filters so far, for `skipped_count`.
+    skipped: usize,
+    /// The path of the last entry popped, see `with_borrowed_paths`.
+    scratch: Option<ScratchPath>,
+}
+
+impl<N: Entry> Walk<N> {
+    fn new(path: OsString, root_dir: Option<SharedDir>, options: WalkOptions) -> Self {
+        let path = normalize_root(path);
+
+        let mut node = N::root(root_dir.as_deref(), &path);
+        if let Some(ref base) = options.relative_to {
+            node.relabel_root(&relative_root(&path, base));
+        }
+
+        let walk_stack = vec![Pending {
+            node,
+            parent_dir: root_dir.clone(),
+            file_type: None,
+            advised: false,
+            glob: GlobState::start(&options.globs),
+            depth: 0,
+            dot: false,
+            permit: None,
+            file: false,
+        }];
+
+        Walk {
+            path,
+            root_dir,
+            options,
+            walk_stack,
+            files_buf: Vec::new(),
+            deferred_errors: VecDeque::new(),
+            last_dir: None,
+            visited_dirs: 0,
+            skipped: 0,
+            scratch: None,
+        }
+    }
+
+    fn with_entry<N2: Entry>(self) -> Walk<N2> {
+        Walk::new(self.path, self.root_dir, self.options)
+    }
+
+    /// Follow symlinks.
+    ///
+    /// This may lead across filesystem boundaries and outside of the specified directory tree.
+    pub fn follow_symlinks(mut self) -> Self {
+        self.options.follow_symlinks = true;
+        self.options.dir_symlinks_only = false;
+        self
+    }
+
+    /// Follow symlinks to directories, but yield symlinks to anything else as symlinks, including
+    /// dangling ones. Telling them apart costs a `stat()` of each symlink, and of each entry on
+    /// filesystems that don't report the file type in `readdir`.
+    ///
+    /// This may lead across filesystem boundaries and outside of the specified directory tree.
+    pub fn follow_dir_symlinks(mut self) -> Self {
+        self.options.follow_symlinks = true;
+        self.options.dir_symlinks_only = true;
+        self
+    }
+
+    /// Do not follow symlinks (default). Symlinks are then skipped silently, unless
+    /// `report_skipped_symlinks` is set.
+    pub fn no_follow_symlinks(mut self) -> Self {
+        self.options.follow_symlinks = false;
+        self.options.dir_symlinks_only = false;
+        self
+    }
+
+    /// Yield the symlinks that are not followed as entries, instead of skipping them. This
+    /// includes symlinks to directories, which are still not descended into.
+    /// `FileEntry::file_type` is `S_IFLNK` for them.
+    ///
+    /// ```rust
+    /// use nix::sys::stat::SFlag;
+    ///
+    /// for entry in fdwalk::walk(".").with_paths().report_skipped_symlinks() {
+    ///     let entry = entry.unwrap();
+    ///     if entry.file_type() == Some(SFlag::S_IFLNK) {
+    ///         println!("not following {}", entry.to_path().display());
+    ///     }
+    /// }
+    /// ```
+    pub fn report_skipped_symlinks(mut self) -> Self {
+        self.options.yield_symlinks = true;
+        self
+    }
+
+    /// Also yield directories, including the root, before their contents. The root is always
+    /// the first entry:
+    ///
+    /// ```rust
+    /// let root = fdwalk::walk("src").with_paths().include_dirs().next().unwrap().unwrap();
+    /// assert_eq!(root.to_path(), std::path::Path::new("src"));
+    /// assert!(root.is_directory_entry());
+    /// ```
+    pub fn include_dirs(mut self) -> Self {
+        self.options.include_dirs = true;
+        self
+    }
+
+    /// Do not yield directories, only what is in them (default).
+    pub fn no_include_dirs(mut self) -> Self {
+        self.options.include_dirs = false;
+        self
+    }
+
+    /// Add flags to every `openat` that is used to open directories.
+    ///
+    /// Directories are always opened with `O_DIRECTORY`. Useful flags to add are `O_CLOEXEC` and
+    /// (on Linux) `O_NOATIME`, although the latter fails with `EPERM` for directories you don't
+    /// own. `O_NOFOLLOW` is ignored, use
+    /// `follow_symlinks` and `no_follow_symlinks` instead. Flags that change the access mode or
+    /// create files make no sense here.
+    pub fn dir_open_flags(mut self, flags: OFlag) -> Self {
+        self.options.dir_open_flags = Some(flags);
+        self
+    }
+
+    /// Stop the walk as soon as `token` is set, for example from a signal handler.
+    ///
+    /// The flag is checked before each entry. Once it is set, all open directories are closed and
+    /// the iterator returns `None`.
+    pub fn with_cancel(mut self, token: Arc<AtomicBool>) -> Self {
+        self.options.cancel = Some(token);
+        self
+    }
+
+    /// Stop the walk once `when` has passed.
+    ///
+    /// The deadline is checked between entries, it can't interrupt a single syscall that is
+    /// blocking (e.g. on a hanging network filesystem).
+    pub fn deadline(mut self, when: Instant) -> Self {
+        self.options.deadline = Some(when);
+        self
+    }
+
+    /// Only yield entries whose name ends with `suffix`, e.g. `b".gz"`.
+    ///
+    /// Directories are still descended into. Multiple name filters must all match.
+    pub fn name_suffix(mut self, suffix: &[u8]) -> Self {
+        self.options
+            .name_filters
+            .push(NameFilter::Suffix(suffix.to_owned()));
+        self
+    }
+
+    /// Only yield entries whose name starts with `prefix`, e.g. `b"app-"`.
+    ///
+    /// Directories are still descended into. Multiple name filters must all match.
+    pub fn name_prefix(mut self, prefix: &[u8]) -> Self {
+        self.options
+            .name_filters
+            .push(NameFilter::Prefix(prefix.to_owned()));
+        self
+    }
+
+    /// Compare names ASCII-case-insensitively in name filters such as `name_suffix`.
+    ///
+    /// Non-ASCII characters still have to match exactly. This does not affect anything but name
+    /// filters.
+    pub fn case_insensitive(mut self) -> Self {
+        self.options.case_insensitive = true;
+        self
+    }
+
+    /// Compare names exactly in name filters (default).
+    pub fn case_sensitive(mut self) -> Self {
+        self.options.case_insensitive = false;
+        self
+    }
+
+    /// Yield directories that can't be opened due to missing permissions as entries, instead of
+    /// returning an error.
+    ///
+    /// Such entries are directories according to `FileEntry::file_type`, but
+    /// `FileEntry::is_readable` returns false for them. This costs one `stat()` per permission
+    /// error. Files that can't be opened are yielded like any other file.
+    pub fn report_unreadable_dirs(mut self) -> Self {
+        self.options.report_unreadable_dirs = true;
+        self
+    }
+
+    /// Return an error for directories that can't be opened (default).
+    pub fn no_report_unreadable_dirs(mut self) -> Self {
+        self.options.report_unreadable_dirs = false;
+        self
+    }
+
+    /// Make paths relative to `base` instead of the current directory. If the root of the walk is
+    /// not below `base`, paths are left as they are.
+    ///
+    /// This only compares paths, so `base` needs to be spelled like the root, e.g. both relative
+    /// or both absolute.
+    ///
+    /// ```rust
+    /// // yields `src/lib.rs` and so on, instead of `./src/lib.rs`
+    /// let walk = fdwalk::walk("./src").with_paths().relative_to(".".into());
+    /// ```
+    pub fn relative_to(mut self, base: PathBuf) -> Self {
+        let root = relative_root(&self.path, &base);
+        for pending in &mut self.walk_stack {
+            if pending.depth == 0 {
+                pending.node.relabel_root(&root);
+            }
+        }
+        self.options.relative_to = Some(base);
+        self
+    }
+
+    /// Return `WalkError::SymlinkRoot` instead of walking anything if the root itself is a
+    /// symlink, regardless of `follow_symlinks`.
+    ///
+    /// Only the last component of the root path is checked, `walk("link/dir")` still goes
+    /// through `link`.
+    pub fn reject_symlink_root(mut self) -> Self {
+        self.options.reject_symlink_root = true;
+        self
+    }
+
+    /// Return `WalkError::TreeChanged` when the tree is modified during the walk, instead of
+    /// silently skipping what vanished.
+    ///
+    /// This is best-effort, not a snapshot: a directory's `st_mtime` and `st_ctime` are compared
+    /// before and after reading it, and entries that disappear before they can be opened are
+    /// reported. Changes to a directory after it has been read, and to files that the walk never
+    /// needs to open, go unnoticed. The walk continues after the error.
+    pub fn detect_mutation(mut self) -> Self {
+        self.options.detect_mutation = true;
+        self
+    }
+
+    /// List only the entries directly inside the root, like `ls` instead of `find`.
+    ///
+    /// Subdirectories are opened but not read, so with `include_dirs` they are still yielded as
+    /// directory entries.
+    ///
+    /// ```rust
+    /// for entry in fdwalk::walk(".").with_paths().include_dirs().no_recurse() {
+    ///     println!("{}", entry.unwrap().to_path().display());
+    /// }
+    /// ```
+    pub fn no_recurse(self) -> Self {
+        self.max_depth(1)
+    }
+
+    /// Don't read directories at depth `n` or deeper, where the root is at depth 0 and the
+    /// entries directly inside it at depth 1. `max_depth(1)` is `no_recurse`, and `max_depth(0)`
+    /// yields at most the root itself.
+    ///
+    /// Directories at depth `n` are opened but not read, so with `include_dirs` they are still
+    /// yielded, like `find -maxdepth`.
+    pub fn max_depth(mut self, n: usize) -> Self {
+        self.options.max_depth = Some(n);
+        self
+    }
+
+    /// Also yield the `.` and `..` entries of every directory that is read, e.g. to emulate
+    /// `ls -a` together with `no_recurse`. They are never descended into, and yielded like files,
+    /// so `FileEntry::is_directory_entry` is false for them.
+    pub fn include_dot_entries(mut self) -> Self {
+        self.options.include_dot_entries = true;
+        self
+    }
+
+    /// Only yield regular files, and skip FIFOs, sockets, devices and symlinks, e.g. so that
+    /// opening every entry can't block on a FIFO. With `follow_symlinks`, symlinks to regular
+    /// files are yielded.
+    ///
+    /// This goes by `d_type`, and costs a `stat()` per entry where it is unknown, and per
+    /// followed symlink. `include_dirs` and `include_dot_entries` are ignored, since directories
+    /// are not regular files.
+    pub fn regular_files_only(mut self) -> Self {
+        self.options.regular_files_only = true;
+        self
+    }
+
+    /// Call `f` with the `(st_dev, st_ino)` of each directory before it is read, e.g. for
+    /// custom cycle detection. The path is that of the directory if paths are tracked, and its
+    /// name otherwise.
+    ///
+    /// In a parallel walk, `f` is called from all threads, but never concurrently.
+    ///
+    /// ```rust
+    /// let walk = fdwalk::walk(".").on_dir_enter_id(|dev, ino, path| {
+    ///     println!("{}:{} {}", dev, ino, path.display());
+    /// });
+    /// ```
+    pub fn on_dir_enter_id<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(u64, u64, &Path) + Send + 'static,
+    {
+        self.options.on_dir_enter = Some(Arc::new(Mutex::new(f)));
+        self
+    }
+
+    /// Tell the walk how many directories it is going to open, to get a `progress` estimate.
+    /// Take the number from `count_files` on an otherwise identical walk with `include_dirs`.
+    pub fn with_total_dirs(mut self, n: u64) -> Self {
+        self.options.total_dirs = Some(n);
+        self
+    }
+
+    /// How far along the walk is, from 0.0 to 1.0, as the share of the directories passed to
+    /// `with_total_dirs` that have been opened. Without that, this is always 0.0.
+    ///
+    /// Files are not taken into account, so this is only as good as directories are of equal
+    /// size. It is also approximate as the tree may change between counting and walking, or a
+    /// parallel walk may hand off directories to other threads.
+    ///
+    /// ```rust,no_run
+    /// let total = fdwalk::walk(".").include_dirs().count_files().unwrap().dirs;
+    /// let mut walk = fdwalk::walk(".").with_total_dirs(total);
+    /// while let Some(entry) = walk.next() {
+    ///     entry.unwrap();
+    ///     println!("{:.0}%", walk.progress() * 100.0);
+    /// }
+    /// ```
+    pub fn progress(&self) -> f64 {
+        match self.options.total_dirs {
+            Some(0) => 1.0,
+            Some(total) => (self.visited_dirs as f64 / total as f64).min(1.0),
+            None => 0.0,
+        }
+    }
+
+    /// How many entries the name, glob and stat filters have excluded so far. Directories that
+    /// no glob can match below count as one entry, since they are not read.
+    ///
+    /// ```rust
+    /// let mut walk = fdwalk::walk(".").name_suffix(b".rs");
+    /// let yielded = walk.by_ref().count();
+    /// println!("yielded {}, skipped {}", yielded, walk.skipped_count());
+    /// ```
+    pub fn skipped_count(&self) -> usize {
+        self.skipped
+    }
+
+    /// Don't follow magic links like `/proc/PID/root` or `/proc/PID/cwd` into directories, which
+    /// could otherwise lead the walk into the filesystem of another mount namespace. This only
+    /// makes a difference together with `follow_symlinks`, such links are skipped like dangling
+    /// ones.
+    ///
+    /// Directories are opened with `openat2` and `RESOLVE_NO_MAGICLINKS` for this, so it does
+    /// nothing on kernels without `openat2` (before Linux 5.6) and on other platforms.
+    ///
+    /// ```rust
+    /// let walk = fdwalk::walk("/proc/self").follow_symlinks().no_magiclinks();
+    /// ```
+    pub fn no_magiclinks(mut self) -> Self {
+        self.options.no_magiclinks = true;
+        self
+    }
+
+    /// Return `WalkError::MaxDepthExceeded` instead of reading directories at depth `n` or
+    /// deeper, as a safety valve against trees that are nested without end, e.g. through bind
+    /// mounts or followed symlinks. The default is 4096.
+    ///
+    /// Unlike `max_depth`, this is reported, and `count_files` stops there.
+    pub fn depth_limit(mut self, n: usize) -> Self {
+        self.options.depth_limit = Some(n);
+        self
+    }
+
+    /// Read at most `n` entries from each directory, as a safety valve against huge directories on
+    /// untrusted filesystems.
+    ///
+    /// The remaining entries of such a directory are omitted silently, unless
+    /// `report_truncated_dirs` is set.
+    pub fn max_entries_per_dir(mut self, n: usize) -> Self {
+        self.options.max_entries_per_dir = Some(n);
+        self
+    }
+
+    /// Return `WalkError::DirTruncated` for directories that hit `max_entries_per_dir`. The
+    /// entries that were read are yielded as usual.
+    pub fn report_truncated_dirs(mut self) -> Self {
+        self.options.report_truncated_dirs = true;
+        self
+    }
+
+    /// Omit entries beyond `max_entries_per_dir` silently (default).
+    pub fn no_report_truncated_dirs(mut self) -> Self {
+        self.options.report_truncated_dirs = false;
+        self
+    }
+
+    /// Count the entries that iterating this walk would yield, by type, without constructing
+    /// any `FileEntry`s. All options apply like they do to iteration, so with `include_dirs` the
+    /// root is counted in `dirs` too, and `.` and `..` from `include_dot_entries` are as well.
+    ///
+    /// File types come from `d_type` where possible, so this only costs a `stat()` per entry on
+    /// filesystems that don't report it, and per symlink if `follow_symlinks` is set.
+    ///
+    /// Directories below the root that can't be opened due to missing permissions are skipped,
+    /// unless `report_unreadable_dirs` is set, in which case they are counted in `dirs` like they
+    /// are yielded then. Stops at the first other error iteration would yield.
+    pub fn count_files(mut self) -> Result<WalkCounts, WalkError> {
+        let report_unreadable = self.options.report_unreadable_dirs;
+        self.options.report_unreadable_dirs = true;
+        let follow = self.options.follow_symlinks;
+
+        let mut counts = WalkCounts::default();
+        for entry in self.with_entry::<Counted>() {
+            let entry = entry?;
+            if entry.is_unreadable_root() {
+                return Err(Errno::EACCES.into());
+            }
+            if entry.is_unreadable() && !report_unreadable {
+                continue;
+            }
+            counts.add(entry.counted_type(follow));
+        }
+        Ok(counts)
+    }
+
+    /// Turn an error from opening a directory into a `WalkError`.
+    fn open_error(&self, errno: Errno) -> WalkError {
+        match errno {
+            Errno::ENOSYS if self.options.sandboxed => WalkError::SandboxUnsupported,
+            e => e.into(),
+        }
+    }
+
+    /// Only yield entries whose path relative to the root matches `pattern`, and don't descend
+    /// into directories that can't contain such entries. Can be called multiple times, entries
+    /// then need to match any of the patterns.
+    ///
+    /// Patterns are split at `/`. `**` as a whole component matches any number of components,
+    /// `*` any part of a name, `?` any single byte and `[a-z]`/`[!a-z]` a class of bytes. A
+    /// backslash escapes the next character. A directory matching a pattern does not imply that
+    /// its contents do, use `dir/**` for that. `case_insensitive` applies here as well.
+    ///
+    /// ```rust
+    /// let walk = fdwalk::walk(".").include_glob("src/**/*.rs").unwrap();
+    /// ```
+    pub fn include_glob(mut self, pattern: &str) -> Result<Self, GlobError> {
+        self.options.globs.push(Glob::new(pattern)?);
+        // the root is already on the stack
+        for pending in &mut self.walk_stack {
+            pending.glob = GlobState::start(&self.options.globs);
+        }
+        Ok(self)
+    }
+
+    /// Pull up to `n` entries, fewer at the end of the walk, and an empty vector after it.
+    ///
+    /// If an error happens after some entries have been pulled, those entries are returned, and
+    /// the error is returned by the next call.
+    ///
+    /// ```rust
+    /// let mut walk = fdwalk::walk(".");
+    /// loop {
+    ///     let chunk = walk.next_chunk(100).unwrap();
+    ///     if chunk.is_empty() {
+    ///         break;
+    ///     }
+    ///     println!("{} more files", chunk.len());
+    /// }
+    /// ```
+    pub fn next_chunk(&mut self, n: usize) -> Result<Vec<N>, WalkError> {
+        // `n` may well be `usize::MAX` to drain everything
+        let mut rv = Vec::with_capacity(n.min(1024));
+        while rv.len() < n {
+            match self.next() {
+                Some(Ok(x)) => rv.push(x),
+                Some(Err(e)) if rv.is_empty() => return Err(e),
+                Some(Err(e)) => {
+                    self.deferred_errors.push_front(e);
+                    break;
+                }
+                None => break,
+            }
+        }
+        Ok(rv)
+    }
+
+    /// Whether `f` returns true for any entry. Stops at the first such entry, or at the first
+    /// error, which is returned. Unlike `Iterator::any`, `f` only ever sees entries.
+    ///
+    /// ```rust,no_run
+    /// let huge = fdwalk::walk(".")
+    ///     .with_open()
+    ///     .any_entry(|entry| entry.stat().map_or(false, |x| x.st_size > 1 << 30))
+    ///     .unwrap();
+    /// ```
+    pub fn any_entry<F: FnMut(&N) -> bool>(self, mut f: F) -> Result<bool, WalkError> {
+        for entry in self {
+            if f(&entry?) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether `f` returns true for all entries. Stops at the first entry for which it doesn't,
+    /// or at the first error, which is returned.
+    pub fn all_entries<F: FnMut(&N) -> bool>(self, mut f: F) -> Result<bool, WalkError> {
+        for entry in self {
+            if !f(&entry?) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Apply `f` to each entry, and pass errors through unchanged.
+    ///
+    /// ```rust
+    /// for path in fdwalk::walk(".").with_paths().map_entry(|entry| entry.to_path()) {
+    ///     println!("{}", path.unwrap().display());
+    /// }
+    /// ```
+    pub fn map_entry<T, F: FnMut(N) -> T>(
+        self,
+        mut f: F,
+    ) -> impl Iterator<Item = Result<T, WalkError>> {
+        self.map(move |entry| entry.map(&mut f))
+    }
+
+    /// Call `f` with each entry, stopping at the first error of either the walk or `f`.
+    ///
+    /// Unlike `Iterator::try_for_each`, walk errors are converted with `From` too, so both can be
+    /// handled with `?`:
+    ///
+    /// ```rust
+    /// use std::error::Error;
+    ///
+    /// fn sizes() -> Result<(), Box<dyn Error>> {
+    ///     fdwalk::walk(".").with_open().try_for_each_entry(|entry| {
+    ///         println!("{}", entry.stat()?.st_size);
+    ///         Ok(())
+    ///     })
+    /// }
+    /// # sizes().unwrap();
+    /// ```
+    pub fn try_for_each_entry<E, F>(self, mut f: F) -> Result<(), E>
+    where
+        E: From<WalkError>,
+        F: FnMut(N) -> Result<(), E>,
+    {
+        for entry in self {
+            f(entry?)?;
+        }
+        Ok(())
+    }
+
+    /// Yield only directories, including the root, and no files.
+    pub fn dirs_only(mut self) -> impl Iterator<Item = Result<N, WalkError>> {
+        self.options.include_dirs = true;
+        self.options.skip_files = true;
+        self
+    }
+
+    fn nofollow_flag(&self) -> OFlag {
+        if self.options.follow_symlinks {
+            OFlag::empty()
+        } else {
+            OFlag::O_NOFOLLOW
+        }
+    }
+
+    /// The flags for opening directories.
+    fn open_flags(&self) -> OFlag {
+        let extra = self.options.dir_open_flags.unwrap_or_else(OFlag::empty);
+        self.nofollow_flag() | OFlag::O_DIRECTORY | (extra - OFlag::O_NOFOLLOW)
+    }
+
+    /// `stat()` an entry, following symlinks only if the walk does.
+    fn stat_flags(&self, parent_fd: RawFd, segment: &OsStr) -> Result<FileStat, Errno> {
+        let flags = if self.options.follow_symlinks {
+            AtFlags::empty()
+        } else {
+            AtFlags::AT_SYMLINK_NOFOLLOW
+        };
+        retry_eintr(|| fstatat(parent_fd, segment, flags))
+    }
+
+    /// Return a non-directory entry if it should be yielded.
+    fn yield_file(&mut self, node: N, glob: &GlobState) -> Option<N> {
+        if self.options.skip_files || !self.is_wanted(&node, glob) {
+            return None;
+        }
+        if self.options.readahead > 0 {
+            self.advise_pending();
+        }
+        Some(node)
+    }
+
+    fn is_symlink(&self, parent_fd: RawFd, segment: &OsStr) -> bool {
+        match retry_eintr(|| fstatat(parent_fd, segment, AtFlags::AT_SYMLINK_NOFOLLOW)) {
+            Ok(stat) => SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFLNK,
+            Err(_) => false,
+        }
+    }
+
+    /// Return a symlink that is not followed if it should be yielded.
+    fn yield_symlink(&mut self, mut node: N, glob: &GlobState) -> Option<N> {
+        if !self.options.yield_symlinks || self.options.regular_files_only {
+            return None;
+        }
+        node.mark_symlink();
+        self.yield_file(node, glob)
+    }
+
+    /// Whether `regular_files_only` skips a non-directory whose `d_type` was inconclusive.
+    fn is_special(&self, parent_fd: RawFd, segment: &OsStr) -> bool {
+        self.options.regular_files_only
+            && !matches!(
+                self.stat_flags(parent_fd, segment),
+                Ok(stat) if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFREG
+            )
+    }
+
+    /// The glob state of a child called `name`, or `None` if it can be skipped entirely.
+    fn advance_glob(&self, glob: &GlobState, name: &[u8]) -> Option<GlobState> {
+        if self.options.globs.is_empty() {
+            return Some(GlobState::default());
+        }
+        let glob = glob.advance(&self.options.globs, name, self.options.case_insensitive);
+        if glob.is_dead() {
+            None
+        } else {
+            Some(glob)
+        }
+    }
+
+    /// Whether a directory is excluded by `Walk::skip_inodes`, and shouldn't even be read.
+    fn is_skipped_dir(&self, dir: &Dir) -> bool {
+        let filters = &self.options.stat_filters;
+        if !filters
+            .iter()
+            .any(|x| matches!(x, StatFilter::SkipInodes(_)))
+        {
+            return false;
+        }
+
+        let id = match fstat(dir.as_raw_fd()) {
+            Ok(stat) => file_id(&stat),
+            Err(_) => return false,
+        };
+        filters.iter().any(|x| match x {
+            StatFilter::SkipInodes(set) => set.contains(&id),
+            _ => false,
+        })
+    }
+
+    /// Whether to read a directory at `depth`, or only yield it.
+    fn is_descended(&self, depth: usize) -> bool {
+        match self.options.max_depth {
+            Some(max) => depth < max,
+            None => true,
+        }
+    }
+
+    /// Whether reading a directory at `depth` is an error, see `depth_limit`.
+    fn is_too_deep(&self, depth: usize) -> bool {
+        depth >= self.options.depth_limit.unwrap_or(DEFAULT_DEPTH_LIMIT)
+    }
 
-    /// Get the current path segment.
-    fn segment(&self) -> &OsStr;
+    /// Whether the walk has been cancelled or ran past its deadline.
+    fn is_cancelled(&self) -> bool {
+        let cancelled = match self.options.cancel {
+            Some(ref cancel) => cancel.load(Ordering::Relaxed),
+            None => false,
+        };
+        cancelled || self.options.deadline.is_some_and(|x| Instant::now() >= x)
+    }
 
-    /// Create a child entry based on current one.
-    fn new_child(&self, parent_dir: &Arc<Dir>, segment: &OsStr) -> Self;
-}
+    /// Whether an entry passes all filters and should be yielded, counting it for
+    /// `skipped_count` otherwise.
+    fn is_wanted(&mut self, node: &N, glob: &GlobState) -> bool {
+        let wanted = self.passes_filters(node, glob);
+        if !wanted {
+            self.skipped += 1;
+        }
+        wanted
+    }
 
-/// The iterator returned from `walk`. Use its methods to configure directory walking.
-pub struct Walk<N: Entry = FileEntry> {
-    path: OsString,
-    follow_symlinks: bool,
-    walk_stack: Vec<(N, Option<Arc<Dir>>)>,
-}
+    fn passes_filters(&self, node: &N, glob: &GlobState) -> bool {
+        if !self.is_wanted_name(node.segment().as_bytes(), glob) {
+            return false;
+        }
+        if self.options.stat_filters.is_empty() {
+            return true;
+        }
+        match node.stat() {
+            Some(Ok(stat)) => self.options.stat_filters.iter().all(|x| x.matches(&stat)),
+            // most likely deleted in the meantime
+            _ => false,
+        }
+    }
 
-impl<N: Entry> Walk<N> {
-    fn new(path: OsString, follow_symlinks: bool) -> Self {
-        let walk_stack = vec![(N::root(&path), None)];
+    fn is_wanted_name(&self, name: &[u8], glob: &GlobState) -> bool {
+        if !self.options.globs.is_empty() && !glob.is_match(&self.options.globs) {
+            return false;
+        }
+        self.options
+            .name_filters
+            .iter()
+            .all(|x| x.matches(name, self.options.case_insensitive))
+    }
 
-        Walk {
-            path,
-            follow_symlinks,
-            walk_stack,
+    /// Whether a directory entry is a directory, using `d_type` if possible and opening it
+    /// otherwise. Entries that can't be opened, e.g. dangling symlinks, are not directories.
+    fn is_dir(&self, parent_dir: &Dir, segment: &OsStr, dirent: &Dirent) -> bool {
+        match dirent.file_type() {
+            Some(Type::Directory) => true,
+            Some(Type::Symlink) if self.options.follow_symlinks => {
+                self.probe_dir(parent_dir, segment)
+            }
+            Some(_) => false,
+            None => self.probe_dir(parent_dir, segment),
         }
     }
 
-    fn with_entry<N2: Entry>(self) -> Walk<N2> {
-        Walk::new(self.path, self.follow_symlinks)
+    fn probe_dir(&self, parent_dir: &Dir, segment: &OsStr) -> bool {
+        self.open_dir(parent_dir.as_raw_fd(), segment, self.open_flags())
+            .is_ok()
     }
 
-    /// Follow symlinks.
-    ///
-    /// This may lead across filesystem boundaries and outside of the specified directory tree.
-    pub fn follow_symlinks(mut self) -> Self {
-        self.follow_symlinks = true;
-        self
+    /// Open a directory, with `openat2` if the walk is sandboxed or avoids magic links.
+    fn open_dir(&self, parent_fd: RawFd, segment: &OsStr, oflags: OFlag) -> Result<Dir, Errno> {
+        retry_eintr(|| self.open_dir_once(parent_fd, segment, oflags))
     }
 
-    /// Do not follow symlinks (default).
-    pub fn no_follow_symlinks(mut self) -> Self {
-        self.follow_symlinks = false;
-        self
+    fn open_dir_once(
+        &self,
+        parent_fd: RawFd,
+        segment: &OsStr,
+        oflags: OFlag,
+    ) -> Result<Dir, Errno> {
+        #[cfg(target_os = "linux")]
+        if self.options.sandboxed {
+            let mut resolve = RESOLVE_BENEATH;
+            if !self.options.follow_symlinks {
+                resolve |= RESOLVE_NO_SYMLINKS;
+            }
+            if self.options.no_magiclinks {
+                resolve |= RESOLVE_NO_MAGICLINKS;
+            }
+            return openat2_dir(parent_fd, segment, oflags, resolve);
+        } else if self.options.no_magiclinks && !OPENAT2_MISSING.load(Ordering::Relaxed) {
+            match openat2_dir(parent_fd, segment, oflags, RESOLVE_NO_MAGICLINKS) {
+                Err(Errno::ENOSYS) => OPENAT2_MISSING.store(true, Ordering::Relaxed),
+                x => return x,
+            }
+        }
+
+        Dir::openat(parent_fd, segment, oflags, Mode::empty())
+    }
+
+    /// Issue `POSIX_FADV_WILLNEED` for the regular files among the next `readahead` entries on
+    /// the stack that have not been advised yet. Errors are ignored, this is only a hint.
+    fn advise_pending(&mut self) {
+        let oflags = self.nofollow_flag()
+            | OFlag::O_RDONLY
+            | OFlag::O_NONBLOCK
+            | OFlag::O_NOCTTY
+            | OFlag::O_CLOEXEC;
+
+        for pending in self
+            .walk_stack
+            .iter_mut()
+            .rev()
+            .take(self.options.readahead)
+        {
+            // opening anything else may have side effects, e.g. on devices
+            if pending.advised || pending.file_type != Some(Type::File) {
+                continue;
+            }
+            pending.advised = true;
+
+            let parent_dir = match pending.parent_dir {
+                Some(ref x) => x,
+                None => continue,
+            };
+
+            if let Ok(fd) = openat(
+                parent_dir.as_raw_fd(),
+                pending.node.segment(),
+                oflags,
+                Mode::empty(),
+            ) {
+                #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+                let _ = nix::fcntl::posix_fadvise(
+                    fd,
+                    0,
+                    0,
+                    nix::fcntl::PosixFadviseAdvice::POSIX_FADV_WILLNEED,
+                );
+                let _ = close(fd);
+            }
+        }
     }
 }
 
-impl<D: OpenProvider, P: PathProvider> Walk<FileEntry<D, P>> {
+// Only options that select providers, or that need them, live here. Everything else is on
+// `impl<N: Entry> Walk<N>`, so that it works with custom entries too.
+impl<D: OpenProvider, P: PathProvider, E: DirentProvider> Walk<FileEntry<D, P, E>> {
     /// Enable ability to get the path of the currrent file entry.
     ///
     /// This increases memory usage as now paths need to be kept in memory.
-    pub fn with_paths(self) -> Walk<FileEntry<D, WithPath>> {
+    pub fn with_paths(self) -> Walk<FileEntry<D, WithPath, E>> {
+        self.with_entry()
+    }
+
+    /// Like `with_paths`, but store identical path segments only once, which saves memory on trees
+    /// where the same long names repeat a lot.
+    ///
+    /// Segments are shared through a process-wide table behind a lock, so this is slower than
+    /// `with_paths`. Names shorter than 24 bytes are stored inline by `with_paths` anyway, and
+    /// interning them saves little.
+    pub fn with_interned_paths(self) -> Walk<FileEntry<D, WithInternedPath, E>> {
         self.with_entry()
     }
 
     /// Disables ability to get the path of the current file entry (default).
-    pub fn without_paths(self) -> Walk<FileEntry<D, WithoutPath>> {
+    pub fn without_paths(self) -> Walk<FileEntry<D, WithoutPath, E>> {
         self.with_entry()
     }
 
     /// Enable ability to open file directly from entry.
     ///
     /// This makes `open` and `open_options` available.
-    pub fn with_open(self) -> Walk<FileEntry<WithOpen, P>> {
+    pub fn with_open(self) -> Walk<FileEntry<WithOpen, P, E>> {
         self.with_entry()
     }
 
     /// Disables ability to open file from entry (default).
     ///
     /// With neither paths nor open enabled, a file entry can only be used to count files.
-    pub fn without_open(self) -> Walk<FileEntry<WithoutOpen, P>> {
+    pub fn without_open(self) -> Walk<FileEntry<WithoutOpen, P, E>> {
+        self.with_entry()
+    }
+
+    /// Keep the directory entry each file was found with, see `FileEntry::dirent`.
+    ///
+    /// This makes the inode number and file type reported by `getdents` available without a
+    /// `stat()`.
+    pub fn with_dirent(self) -> Walk<FileEntry<D, P, WithDirent>> {
+        self.with_entry()
+    }
+
+    /// Do not keep directory entries around (default).
+    pub fn without_dirent(self) -> Walk<FileEntry<D, P, WithoutDirent>> {
         self.with_entry()
     }
+
+    /// Hash the entire tree into a single value, to tell cheaply whether anything changed since
+    /// the last time.
+    ///
+    /// Each entry is hashed into its own clone of `hasher`. Those hashes are sorted by the path
+    /// of the entry relative to the root and then fed into `hasher` together with the paths, so
+    /// the result neither depends on the order of `readdir` nor on where the tree is located.
+    /// The paths of all entries are kept in memory for that. Walk options apply as usual, e.g.
+    /// set `include_dirs` to notice empty directories.
+    ///
+    /// The first error aborts hashing, including entries vanishing during the walk.
+    ///
+    /// The result is only as stable as `hasher`. The algorithm of `DefaultHasher` may change
+    /// with any Rust release, so only use it to compare hashes within the same program, and pick
+    /// a hasher with a fixed algorithm for hashes that are stored.
+    ///
+    /// ```rust,no_run
+    /// use std::collections::hash_map::DefaultHasher;
+    /// use fdwalk::TreeHashInput;
+    ///
+    /// // don't persist this, it may be different after upgrading Rust
+    /// let hash = fdwalk::walk("src")
+    ///     .tree_hash(DefaultHasher::new(), TreeHashInput::Contents)
+    ///     .unwrap();
+    /// println!("{:x}", hash);
+    /// ```
+    pub fn tree_hash<H: Hasher + Clone>(
+        self,
+        mut hasher: H,
+        input: TreeHashInput,
+    ) -> Result<u64, WalkError> {
+        let root = PathBuf::from(&self.path);
+        let mut leaves = Vec::new();
+
+        for entry in self.with_paths().with_open() {
+            let entry = entry?;
+            let mut leaf = hasher.clone();
+            entry.hash_into(&mut leaf, input)?;
+
+            let path = entry.to_path();
+            let path = match path.strip_prefix(&root) {
+                Ok(x) => x.to_owned(),
+                Err(_) => path,
+            };
+            leaves.push((path, leaf.finish()));
+        }
+
+        leaves.sort_unstable_by(|a, b| a.0.as_os_str().as_bytes().cmp(b.0.as_os_str().as_bytes()));
+        for (path, leaf) in leaves {
+            hasher.write(path.as_os_str().as_bytes());
+            // paths can't contain NUL bytes, so this separates them unambiguously
+            hasher.write_u8(0);
+            hasher.write_u64(leaf);
+        }
+        Ok(hasher.finish())
+    }
 }
 
-impl<N: Entry> Iterator for Walk<N> {
-    type Item = Result<N, Errno>;
+impl<D: OpenProvider, E: DirentProvider> Walk<FileEntry<D, WithPath, E>> {
+    /// Yield paths instead of file entries.
+    ///
+    /// ```rust
+    /// for path in fdwalk::walk(".").with_paths().paths() {
+    ///     println!("{}", path.unwrap().display());
+    /// }
+    /// ```
+    pub fn paths(self) -> impl Iterator<Item = Result<PathBuf, WalkError>> {
+        self.map(|entry| entry.map(|x| x.to_path()))
+    }
+
+    /// Walk the entire tree and return all paths, stopping at the first error.
+    pub fn collect_paths(self) -> Result<Vec<PathBuf>, WalkError> {
+        self.paths().collect()
+    }
+
+    /// Walk the entire tree and return all entries sorted by path, stopping at the first error.
+    ///
+    /// Entries are ordered like by their `Ord` impl, byte by byte, so `a-b` comes before `a/b`.
+    /// Each path is built once for sorting, rather than twice per comparison as with
+    /// `Vec::sort`.
+    pub fn into_sorted_vec(self) -> Result<Vec<FileEntry<D, WithPath, E>>, WalkError> {
+        let mut entries = self.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_cached_key(|x| x.to_path().into_os_string());
+        Ok(entries)
+    }
+
+    /// Yield each directory together with the files in it, including directories without any.
+    ///
+    /// A directory is yielded once everything below it has been walked, so after its
+    /// subdirectories. Until then, the files of every directory on the way down are kept in
+    /// memory. Errors are passed through as they happen.
+    ///
+    /// Directories themselves are never part of a group, `include_dirs` is ignored. Directories
+    /// that are excluded by filters are only yielded if they contain files that aren't.
+    /// Directories that can't be opened are reported as errors, `report_unreadable_dirs` is
+    /// ignored as well.
+    pub fn group_by_dir(mut self) -> impl Iterator<Item = Result<DirGroup<D, E>, WalkError>> {
+        // directories mark where a group starts
+        self.options.include_dirs = true;
+        // they would end up among the files of their parent otherwise
+        self.options.report_unreadable_dirs = false;
+        GroupByDir {
+            walk: self,
+            open: Vec::new(),
+            next: None,
+        }
+    }
+}
+
+/// A directory path and the files in it, see `Walk::group_by_dir`.
+type DirGroup<D, E> = (PathBuf, Vec<FileEntry<D, WithPath, E>>);
+
+/// The iterator returned by `Walk::group_by_dir`.
+struct GroupByDir<D: OpenProvider, E: DirentProvider> {
+    walk: Walk<FileEntry<D, WithPath, E>>,
+    /// The groups of the directory the walk is in and of all directories above it.
+    open: Vec<DirGroup<D, E>>,
+    /// An entry that belongs to none of the groups in `open`, put back until they are yielded.
+    next: Option<(PathBuf, FileEntry<D, WithPath, E>)>,
+}
+
+impl<D: OpenProvider, E: DirentProvider> Iterator for GroupByDir<D, E> {
+    type Item = Result<DirGroup<D, E>, WalkError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let (node, parent_dir) = self.walk_stack.pop()?;
+            let (path, entry) = match self.next.take() {
+                Some(x) => x,
+                None => match self.walk.next() {
+                    Some(Ok(x)) => (x.to_path(), x),
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => return self.open.pop().map(Ok),
+                },
+            };
 
-            let oflags = if self.follow_symlinks {
-                OFlag::empty()
-            } else {
-                OFlag::O_NOFOLLOW
+            let parent = match path.parent() {
+                Some(x) if !x.as_os_str().is_empty() => x,
+                _ => Path::new("."),
             };
 
-            let dir = if let Some(parent_dir) = parent_dir {
-                Dir::openat(
-                    parent_dir.as_raw_fd(),
-                    node.segment(),
-                    oflags,
-                    Mode::empty(),
-                )
-            } else {
-                Dir::open(node.segment(), oflags, Mode::empty())
+            // the walk is depth-first, so once it has left a directory it never comes back
+            if let Some((ref dir, _)) = self.open.last() {
+                if !(dir.as_os_str() == "." || parent.starts_with(dir)) {
+                    self.next = Some((path, entry));
+                    return self.open.pop().map(Ok);
+                }
+            }
+
+            if entry.is_directory_entry() {
+                // not read at all with `max_depth`, rather than empty
+                if self.walk.is_descended(entry.depth()) {
+                    self.open.push((path, Vec::new()));
+                }
+                continue;
+            }
+
+            match self.open.last_mut() {
+                Some((ref dir, ref mut files)) if dir == parent => files.push(entry),
+                // the directory itself was filtered out
+                _ => self.open.push((parent.to_owned(), vec![entry])),
+            }
+        }
+    }
+}
+
+impl<P: PathProvider, E: DirentProvider> Walk<FileEntry<WithOpen, P, E>> {
+    /// Yield only symlinks that point to nothing.
+    ///
+    /// This implies `no_follow_symlinks`, and costs a `stat()` per symlink.
+    pub fn broken_symlinks(
+        mut self,
+    ) -> impl Iterator<Item = Result<FileEntry<WithOpen, P, E>, WalkError>> {
+        self.options.follow_symlinks = false;
+        self.options.yield_symlinks = true;
+        self.filter(|entry| match entry {
+            Ok(entry) => {
+                entry.file_type() == Some(SFlag::S_IFLNK)
+                    && entry.target_type() == Err(Errno::ENOENT)
+            }
+            Err(_) => true,
+        })
+    }
+
+    /// Only yield entries owned by `uid`. Directories are descended into regardless.
+    ///
+    /// This costs a `stat()` per entry, which is cached for `FileEntry::stat`.
+    pub fn owned_by(mut self, uid: Uid) -> Self {
+        self.options.stat_filters.push(StatFilter::Uid(uid));
+        self
+    }
+
+    /// Only yield entries whose group is `gid`. Directories are descended into regardless.
+    ///
+    /// This costs a `stat()` per entry, which is cached for `FileEntry::stat`.
+    pub fn group_owned_by(mut self, gid: Gid) -> Self {
+        self.options.stat_filters.push(StatFilter::Gid(gid));
+        self
+    }
+
+    /// Only yield entries where `st_mode & mask == value`. Directories are descended into
+    /// regardless.
+    ///
+    /// This costs a `stat()` per entry, which is cached for `FileEntry::stat`.
+    ///
+    /// ```rust
+    /// use nix::libc::{S_IFMT, S_IFREG, S_IWOTH};
+    ///
+    /// // world-writable regular files
+    /// let walk = fdwalk::walk(".")
+    ///     .with_open()
+    ///     .mode_matches(S_IFMT, S_IFREG)
+    ///     .mode_matches(S_IWOTH, S_IWOTH);
+    /// ```
+    pub fn mode_matches(mut self, mask: libc::mode_t, value: libc::mode_t) -> Self {
+        self.options
+            .stat_filters
+            .push(StatFilter::Mode(mask, value));
+        self
+    }
+
+    /// Only yield entries whose inode changed after `t`, according to `st_ctime`, see
+    /// `FileEntry::changed`. Directories are descended into regardless.
+    ///
+    /// Unlike the modification time, `st_ctime` also changes with permissions and ownership,
+    /// and can't be set back by `touch`, which makes it the better choice for incremental
+    /// backups. This costs a `stat()` per entry, which is cached for `FileEntry::stat`.
+    pub fn changed_after(mut self, t: SystemTime) -> Self {
+        self.options.stat_filters.push(StatFilter::ChangedAfter(t));
+        self
+    }
+
+    /// Skip entries whose `(st_dev, st_ino)` is in `set`, see `FileEntry::file_id`. Directories in
+    /// the set are not descended into either.
+    ///
+    /// This costs a `stat()` per entry, which is cached for `FileEntry::stat`. Symlinks that are
+    /// followed are identified by the symlink itself, directories by what they point to.
+    pub fn skip_inodes(mut self, set: Arc<HashSet<(u64, u64)>>) -> Self {
+        self.options.stat_filters.push(StatFilter::SkipInodes(set));
+        self
+    }
+
+    /// Ask the kernel to start reading the next `n` pending files into the page cache while the
+    /// current file is being processed (`posix_fadvise(POSIX_FADV_WILLNEED)`).
+    ///
+    /// This costs an extra `open` per file and only pays off if you are going to read every file,
+    /// like `examples/cat.rs` does. Only entries that `d_type` reports as regular files are
+    /// opened for this. It does nothing on platforms without `posix_fadvise`.
+    pub fn readahead(mut self, n: usize) -> Self {
+        self.options.readahead = n;
+        self
+    }
+
+    /// Walk the entire tree and return the `n` most recently modified entries, newest first.
+    ///
+    /// Only `n` entries are kept in memory at any time. Stops at the first error, entries that
+    /// disappear before they can be `stat()`ed are skipped.
+    pub fn top_by_mtime(self, n: usize) -> Result<Vec<FileEntry<WithOpen, P, E>>, WalkError> {
+        self.top_by(n, |_, stat| (stat.st_mtime, stat.st_mtime_nsec))
+    }
+
+    /// Walk the entire tree and return the `n` largest entries, largest first.
+    ///
+    /// Only `n` entries are kept in memory at any time. Entries of the same size are ordered by
+    /// path if paths are tracked. Stops at the first error, entries that disappear before they
+    /// can be `stat()`ed are skipped.
+    pub fn largest(self, n: usize) -> Result<Vec<FileEntry<WithOpen, P, E>>, WalkError> {
+        self.top_by(n, |entry, stat| (stat.st_size, Reverse(entry.path())))
+    }
+
+    /// Keep the `n` entries with the largest `key` in a min-heap, and return them in descending
+    /// order.
+    fn top_by<K: Ord>(
+        self,
+        n: usize,
+        key: impl Fn(&FileEntry<WithOpen, P, E>, &FileStat) -> K,
+    ) -> Result<Vec<FileEntry<WithOpen, P, E>>, WalkError> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        // `n` is only an upper bound, don't reserve memory for `usize::MAX` entries up front
+        let mut heap = BinaryHeap::with_capacity(n.saturating_add(1).min(1024));
+
+        for entry in self {
+            let entry = entry?;
+            let key = match entry.stat() {
+                Ok(stat) => key(&entry, &stat),
+                Err(Errno::ENOENT) => continue,
+                Err(e) => return Err(e.into()),
             };
 
-            let dir = match dir {
-                Ok(x) => Arc::new(x),
-                Err(Errno::ENOTDIR) => {
+            heap.push(Reverse(Ranked { key, entry }));
+            if heap.len() > n {
+                heap.pop();
+            }
+        }
+
+        Ok(heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(x)| x.entry)
+            .collect())
+    }
+}
+
+/// An entry ordered by `key` alone, see `Walk::top_by`.
+struct Ranked<K, T> {
+    key: K,
+    entry: T,
+}
+
+impl<K: Ord, T> PartialEq for Ranked<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<K: Ord, T> Eq for Ranked<K, T> {}
+
+impl<K: Ord, T> PartialOrd for Ranked<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T> Ord for Ranked<K, T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl<N: Entry> Iterator for Walk<N> {
+    type Item = Result<N, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.last_dir = None;
+
+        loop {
+            if self.is_cancelled() {
+                self.walk_stack.clear();
+                return None;
+            }
+
+            if let Some(e) = self.deferred_errors.pop_front() {
+                return Some(Err(e));
+            }
+
+            let Pending {
+                node,
+                parent_dir,
+                file_type,
+                glob,
+                depth,
+                dot,
+                // keep the parent's permit as long as `parent_dir`
+                permit: _parent_permit,
+                ..
+            } = self.walk_stack.pop()?;
+
+            if let Some(ref mut scratch) = self.scratch {
+                scratch.enter(depth, node.segment());
+            }
+
+            if dot {
+                // descending into these would never end
+                if !self.options.regular_files_only && self.is_wanted(&node, &glob) {
                     return Some(Ok(node));
                 }
+                continue;
+            }
+
+            let follow = self.options.follow_symlinks;
+
+            // `d_type` often tells us that there is no point in trying to open a directory
+            match file_type {
+                Some(Type::Directory) | None => (),
+                Some(Type::Symlink) if follow => (),
+                Some(Type::Symlink) => match self.yield_symlink(node, &glob) {
+                    Some(node) => return Some(Ok(node)),
+                    None => continue,
+                },
+                Some(Type::File) => match self.yield_file(node, &glob) {
+                    Some(node) => return Some(Ok(node)),
+                    None => continue,
+                },
+                Some(_) if self.options.regular_files_only => continue,
+                Some(_) => match self.yield_file(node, &glob) {
+                    Some(node) => return Some(Ok(node)),
+                    None => continue,
+                },
+            }
+
+            let reject_symlink = depth == 0 && self.options.reject_symlink_root;
+            let mut oflags = self.open_flags();
+            if reject_symlink {
+                oflags |= OFlag::O_NOFOLLOW;
+            }
+
+            let parent_fd = parent_dir
+                .as_ref()
+                .map(|x| x.as_raw_fd())
+                .unwrap_or(libc::AT_FDCWD);
+
+            let permit = self
+                .options
+                .fd_budget
+                .as_ref()
+                .map(|x| Arc::new(x.acquire()));
+            let dir = match self.open_dir(parent_fd, node.segment(), oflags) {
+                Ok(x) => SharedDir::new(x),
+                Err(Errno::ENOTDIR) | Err(Errno::ELOOP)
+                    if reject_symlink && self.is_symlink(parent_fd, node.segment()) =>
+                {
+                    return Some(Err(WalkError::SymlinkRoot(PathBuf::from(&self.path))));
+                }
+                // `O_DIRECTORY | O_NOFOLLOW` fails like this for symlinks too
+                Err(Errno::ENOTDIR)
+                    if !follow
+                        && file_type.is_none()
+                        && self.is_symlink(parent_fd, node.segment()) =>
+                {
+                    match self.yield_symlink(node, &glob) {
+                        Some(node) => return Some(Ok(node)),
+                        None => continue,
+                    }
+                }
+                // `follow_dir_symlinks` with a symlink to a file, or a dangling one
+                Err(Errno::ENOTDIR) | Err(Errno::ENOENT)
+                    if self.options.dir_symlinks_only
+                        && (file_type == Some(Type::Symlink)
+                            || self.is_symlink(parent_fd, node.segment())) =>
+                {
+                    if self.options.regular_files_only {
+                        continue;
+                    }
+                    let mut node = node;
+                    node.mark_symlink();
+                    match self.yield_file(node, &glob) {
+                        Some(node) => return Some(Ok(node)),
+                        None => continue,
+                    }
+                }
+                Err(Errno::ENOTDIR) if self.is_special(parent_fd, node.segment()) => continue,
+                Err(Errno::ENOTDIR) => match self.yield_file(node, &glob) {
+                    Some(node) => return Some(Ok(node)),
+                    None => continue,
+                },
+                // unless this is only a dangling symlink that is followed
+                Err(Errno::ENOENT)
+                    if self.options.detect_mutation
+                        && !self.is_symlink(parent_fd, node.segment()) =>
+                {
+                    return Some(Err(WalkError::TreeChanged(entry_path(&node))));
+                }
                 Err(Errno::ENOENT) => continue,
-                // emitted when follow_symlinks = false and we have a symlink
+                // emitted for symlinks on some platforms when follow_symlinks = false
+                Err(Errno::ELOOP) if !follow => match self.yield_symlink(node, &glob) {
+                    Some(node) => return Some(Ok(node)),
+                    None => continue,
+                },
                 Err(Errno::ELOOP) => continue,
-                Err(e) => return Some(Err(e)),
+                // a followed symlink pointing outside of a sandboxed walk
+                Err(Errno::EXDEV) if self.options.sandboxed => continue,
+                Err(e @ Errno::EACCES) | Err(e @ Errno::EPERM)
+                    if self.options.report_unreadable_dirs =>
+                {
+                    let mut node = node;
+                    match self.stat_flags(parent_fd, node.segment()) {
+                        Ok(stat)
+                            if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT
+                                == SFlag::S_IFDIR =>
+                        {
+                            node.mark_directory(None);
+                            if self.is_wanted(&node, &glob) {
+                                self.last_dir = Some(self.walk_stack.len());
+                                return Some(Ok(node));
+                            }
+                            continue;
+                        }
+                        Ok(stat)
+                            if self.options.regular_files_only
+                                && SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT
+                                    != SFlag::S_IFREG =>
+                        {
+                            continue
+                        }
+                        Ok(_) => match self.yield_file(node, &glob) {
+                            Some(node) => return Some(Ok(node)),
+                            None => continue,
+                        },
+                        // vanished in the meantime
+                        Err(Errno::ENOENT) => continue,
+                        Err(_) => return Some(Err(e.into())),
+                    }
+                }
+                Err(e) => return Some(Err(self.open_error(e))),
             };
 
-            let mut dir_iter =
-                ManuallyDrop::new(Dir::from_fd(dir.as_raw_fd()).unwrap().into_iter());
+            if self.is_skipped_dir(&dir) {
+                continue;
+            }
+            self.visited_dirs += 1;
+
+            let stack_len = self.walk_stack.len();
+            // a directory matching a glob may still not be able to contain anything that does
+            let descend = self.is_descended(depth) && !glob.is_exhausted(&self.options.globs);
+            if descend && self.is_too_deep(depth) {
+                self.deferred_errors
+                    .push_back(WalkError::MaxDepthExceeded(entry_path(&node)));
+            } else if descend {
+                if let Some(ref hook) = self.options.on_dir_enter {
+                    if let Ok(stat) = fstat(dir.as_raw_fd()) {
+                        let (dev, ino) = file_id(&stat);
+                        let mut hook = hook.lock().unwrap_or_else(|e| e.into_inner());
+                        hook(dev, ino, &entry_path(&node));
+                    }
+                }
 
-            for entry in &mut *dir_iter {
-                let entry = match entry {
-                    Ok(x) => x,
-                    Err(e) => return Some(Err(e)),
+                let stamp = if self.options.detect_mutation {
+                    fstat(dir.as_raw_fd()).ok().map(|x| mutation_stamp(&x))
+                } else {
+                    None
                 };
-                let fname = entry.file_name();
-                if fname.to_bytes() == b"." || fname.to_bytes() == b".." {
-                    continue;
+
+                let mut dir_iter =
+                    ManuallyDrop::new(Dir::from_fd(dir.as_raw_fd()).unwrap().into_iter());
+
+                // with `files_first`, non-directories are pushed last so they are popped first
+                let mut files = std::mem::take(&mut self.files_buf);
+                let mut entries = 0;
+                let mut truncated = false;
+                let mut changed = false;
+
+                for entry in &mut *dir_iter {
+                    let entry = match entry {
+                        Ok(x) => x,
+                        // the directory has been deleted while reading it, on some filesystems
+                        Err(Errno::ENOENT) if self.options.detect_mutation => {
+                            changed = fstat(dir.as_raw_fd()).map_or(true, |x| x.st_nlink == 0);
+                            break;
+                        }
+                        Err(Errno::ENOENT) => break,
+                        // a signal arrived, `readdir` can simply be called again
+                        Err(Errno::EINTR) => continue,
+                        Err(e) => return Some(Err(e.into())),
+                    };
+                    let fname = entry.file_name();
+                    let dot = fname.to_bytes() == b"." || fname.to_bytes() == b"..";
+                    if dot && !self.options.include_dot_entries {
+                        continue;
+                    }
+
+                    if Some(entries) == self.options.max_entries_per_dir {
+                        truncated = true;
+                        break;
+                    }
+                    entries += 1;
+
+                    let child_glob = match self.advance_glob(&glob, fname.to_bytes()) {
+                        Some(x) => x,
+                        // neither this entry nor anything below it can match
+                        None => {
+                            self.skipped += 1;
+                            continue;
+                        }
+                    };
+
+                    let segment = OsStr::from_bytes(fname.to_bytes());
+                    let dirent = Dirent::new(&entry);
+                    let mut pending = Pending {
+                        node: node.new_child(&dir, segment, &dirent),
+                        parent_dir: Some(dir.clone()),
+                        file_type: dirent.file_type(),
+                        advised: false,
+                        glob: child_glob,
+                        depth: depth + 1,
+                        dot,
+                        permit: permit.clone(),
+                        file: false,
+                    };
+
+                    if self.options.files_first && (dot || !self.is_dir(&dir, segment, &dirent)) {
+                        pending.file = true;
+                        files.push(pending);
+                    } else {
+                        self.walk_stack.push(pending);
+                    }
+                }
+
+                self.walk_stack.append(&mut files);
+                self.files_buf = files;
+
+                if let Some(stamp) = stamp {
+                    changed |= match fstat(dir.as_raw_fd()) {
+                        Ok(stat) => mutation_stamp(&stat) != stamp,
+                        Err(_) => true,
+                    };
                 }
+                if changed {
+                    self.deferred_errors
+                        .push_back(WalkError::TreeChanged(entry_path(&node)));
+                }
+
+                if truncated && self.options.report_truncated_dirs {
+                    self.deferred_errors
+                        .push_back(WalkError::DirTruncated(entry_path(&node)));
+                }
+            }
 
-                let child = node.new_child(&dir, OsStr::from_bytes(fname.to_bytes()));
-                self.walk_stack.push((child, Some(Arc::clone(&dir))));
+            if self.options.include_dirs && !self.options.regular_files_only {
+                let mut node = node;
+                node.mark_directory(Some(&dir));
+                if self.is_wanted(&node, &glob) {
+                    self.last_dir = Some(stack_len);
+                    return Some(Ok(node));
+                }
             }
         }
     }
 }
 
-unsafe impl<N: Entry> Send for Walk<N> {}
+/// Call `f` again for as long as it is interrupted by signals.
+fn retry_eintr<T>(mut f: impl FnMut() -> Result<T, Errno>) -> Result<T, Errno> {
+    loop {
+        match f() {
+            Err(Errno::EINTR) => continue,
+            x => return x,
+        }
+    }
+}
+
+/// The path of an entry for error messages, or its name if paths aren't tracked.
+fn entry_path<N: Entry>(node: &N) -> PathBuf {
+    node.path().unwrap_or_else(|| PathBuf::from(node.segment()))
+}
+
+/// `struct open_how` for `openat2`, which `libc` does not have yet.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+#[cfg(target_os = "linux")]
+const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+#[cfg(target_os = "linux")]
+const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+#[cfg(target_os = "linux")]
+const RESOLVE_BENEATH: u64 = 0x08;
+
+/// Set once `openat2` failed with `ENOSYS`, so `Walk::no_magiclinks` doesn't keep trying it.
+#[cfg(target_os = "linux")]
+static OPENAT2_MISSING: AtomicBool = AtomicBool::new(false);
+
+/// Open a directory with `openat2` and the given `RESOLVE_*` flags.
+#[cfg(target_os = "linux")]
+fn openat2_dir(
+    parent_fd: RawFd,
+    segment: &OsStr,
+    oflags: OFlag,
+    resolve: u64,
+) -> Result<Dir, Errno> {
+    let segment = std::ffi::CString::new(segment.as_bytes()).map_err(|_| Errno::EINVAL)?;
+    let how = OpenHow {
+        flags: oflags.bits() as u64,
+        mode: 0,
+        resolve,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            parent_fd,
+            segment.as_ptr(),
+            &how as *const OpenHow,
+            std::mem::size_of::<OpenHow>(),
+        )
+    };
+    if fd < 0 {
+        return Err(Errno::last());
+    }
+    Dir::from_fd(fd as RawFd)
+}
+
+/// What changes when entries are added to or removed from a directory.
+#[allow(clippy::unnecessary_cast)]
+fn mutation_stamp(stat: &FileStat) -> [i64; 4] {
+    [
+        stat.st_mtime as i64,
+        stat.st_mtime_nsec as i64,
+        stat.st_ctime as i64,
+        stat.st_ctime_nsec as i64,
+    ]
+}
+
+/// The path the root is displayed as with `Walk::relative_to`.
+fn relative_root(root: &OsStr, base: &Path) -> PathBuf {
+    match Path::new(root).strip_prefix(base) {
+        Ok(x) if x.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(x) => x.to_owned(),
+        Err(_) => PathBuf::from(root),
+    }
+}
+
+/// Turn an empty root into `.` and strip trailing slashes, so that `walk("dir/")` behaves like
+/// `walk("dir")`. A root of `/` is kept as is.
+fn normalize_root(path: OsString) -> OsString {
+    let bytes = path.as_bytes();
+    if bytes.is_empty() {
+        return OsString::from(".");
+    }
+
+    let mut len = bytes.len();
+    while len > 1 && bytes[len - 1] == b'/' {
+        len -= 1;
+    }
+
+    if len == bytes.len() {
+        path
+    } else {
+        OsStr::from_bytes(&bytes[..len]).to_owned()
+    }
+}
 
 /// Start recursively walking the directory given at `path`.
 ///
+/// An empty path means the current directory, just like `"."`. Trailing slashes are ignored. A
+/// path containing a NUL byte makes the walk return `WalkError::Sys(EINVAL)` instead of
+/// panicking, names read from directories can't contain one.
+/// Only the root is opened by path, everything below it relative to its parent directory. To
+/// walk the same directory over and over without resolving its path again each time, open it
+/// once and use `walk_fd`.
+///
 /// To configure the directory walker, use the methods on the return value:
 ///
 ///
 /// ```rust
+/// use fdwalk::walk;
+///
 /// for entry in walk(".").with_paths().follow_symlinks() {
-///     println!("{}", entry.to_path().display());
+///     println!("{}", entry.unwrap().to_path().display());
 /// }
 /// ```
 pub fn walk<P: AsRef<Path>>(path: P) -> Walk {
-    Walk::new(path.as_ref().as_os_str().to_owned(), false)
+    walk_with(path)
+}
+
+/// Like `walk`, but with a custom `Entry` type instead of `FileEntry`.
+///
+/// ```rust
+/// use fdwalk::{Dirent, Entry};
+/// use nix::dir::Dir;
+/// use std::ffi::{OsStr, OsString};
+/// use std::sync::Arc;
+///
+/// /// Only keeps the name of each entry.
+/// struct Name(OsString);
+///
+/// impl Entry for Name {
+///     fn root(_parent_dir: Option<&Arc<Dir>>, segment: &OsStr) -> Self {
+///         Name(segment.to_owned())
+///     }
+///
+///     fn segment(&self) -> &OsStr {
+///         &self.0
+///     }
+///
+///     fn new_child(&self, _parent_dir: &Arc<Dir>, segment: &OsStr, _dirent: &Dirent) -> Self {
+///         Name(segment.to_owned())
+///     }
+/// }
+///
+/// for entry in fdwalk::walk_with::<Name, _>(".").no_recurse() {
+///     println!("{:?}", entry.unwrap().0);
+/// }
+/// ```
+pub fn walk_with<N: Entry, P: AsRef<Path>>(path: P) -> Walk<N> {
+    Walk::new(
+        path.as_ref().as_os_str().to_owned(),
+        None,
+        WalkOptions::default(),
+    )
+}
+
+/// Start recursively walking an already opened directory.
+///
+/// The root entry is `.` relative to `dir`, and paths are relative to it as well. The directory
+/// is opened again to read it, so the stream position of `dir` is not touched.
+///
+/// This is the form to use when the tree may be modified by someone else. The root is never
+/// resolved by path again, and every directory below it is opened relative to its parent's fd
+/// by its name alone, with `O_NOFOLLOW`. A directory that is replaced with a symlink during the
+/// walk is therefore not descended into. Unless `follow_symlinks` is set, the walk only reads
+/// directories that were in the subtree of `dir` when they were opened. It does not protect
+/// against:
+///
+/// * mount points and bind mounts below `dir`, which are walked like any other directory.
+/// * directories that are moved out of the subtree while they are being walked, they are still
+///   walked to the end through the fd the walk has open.
+/// * `FileEntry::open` and friends following a symlink that replaced a file after it was
+///   yielded. Check the result with `File::metadata` if that matters.
+///
+/// `walk_sandboxed` extends this to followed symlinks, which are then not allowed to leave
+/// their parent directory.
+///
+/// `dir` can be reused for any number of walks:
+///
+/// ```rust
+/// use nix::dir::Dir;
+/// use nix::fcntl::OFlag;
+/// use nix::sys::stat::Mode;
+/// use std::sync::Arc;
+///
+/// let dir = Arc::new(Dir::open("src", OFlag::O_DIRECTORY, Mode::empty()).unwrap());
+/// for _ in 0..3 {
+///     let n = fdwalk::walk_fd(dir.clone()).count();
+///     println!("{} files", n);
+/// }
+/// ```
+pub fn walk_fd(dir: Arc<Dir>) -> Walk {
+    Walk::new(
+        OsString::from("."),
+        Some(dir.into()),
+        WalkOptions::default(),
+    )
+}
+
+/// Like `walk_fd`, but guarantee that no directory outside of `dir` is walked, even if `dir`
+/// is writable by someone else who swaps directories for symlinks during the walk.
+///
+/// Every directory is opened with `openat2` and `RESOLVE_BENEATH`, so it must be beneath its
+/// parent, and with `RESOLVE_NO_SYMLINKS` unless `follow_symlinks` is set. Followed symlinks
+/// that point outside of their parent directory are skipped. Only directories are confined:
+/// `FileEntry::open` and friends still use a plain `openat`.
+///
+/// Requires Linux 5.6, older kernels make the walk return `WalkError::SandboxUnsupported`.
+#[cfg(target_os = "linux")]
+pub fn walk_sandboxed(dir: Arc<Dir>) -> Walk {
+    let options = WalkOptions {
+        sandboxed: true,
+        ..WalkOptions::default()
+    };
+    Walk::new(OsString::from("."), Some(dir.into()), options)
 }
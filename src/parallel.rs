@@ -0,0 +1,467 @@
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use crate::{
+    entry_path, DirentProvider, Entry, FileEntry, OpenProvider, Pending, Walk, WalkError, WithPath,
+};
+
+/// Work shared between the threads of a parallel walk.
+struct Shared<N> {
+    queue: Mutex<Queue<N>>,
+    wakeup: Condvar,
+    /// Mirrors `Queue::idle`, so busy threads can check it without locking.
+    idle: AtomicUsize,
+    threads: usize,
+    budget: Option<Arc<FdBudget>>,
+}
+
+struct Queue<N> {
+    pending: Vec<Pending<N>>,
+    idle: usize,
+    done: bool,
+}
+
+impl<N> Shared<N> {
+    fn lock(&self) -> MutexGuard<'_, Queue<N>> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_idle(&self, idle: usize) {
+        self.idle.store(idle, Ordering::Relaxed);
+        if let Some(ref budget) = self.budget {
+            // a thread waiting for a permit may now be the last one that is busy
+            budget.set_idle(idle);
+        }
+    }
+
+    fn finish(&self, queue: &mut Queue<N>) {
+        queue.pending.clear();
+        queue.done = true;
+        self.wakeup.notify_all();
+    }
+}
+
+/// Limits how many directories the threads of a parallel walk have open, see
+/// `ParallelWalk::fd_budget`.
+pub(crate) struct FdBudget {
+    state: Mutex<BudgetState>,
+    released: Condvar,
+    limit: usize,
+    threads: usize,
+}
+
+struct BudgetState {
+    in_use: usize,
+    /// Threads waiting in `acquire`.
+    waiting: usize,
+    /// Threads waiting for work, they can't release anything.
+    idle: usize,
+}
+
+impl FdBudget {
+    fn lock(&self) -> MutexGuard<'_, BudgetState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Take a permit for opening a directory, waiting until one is free.
+    ///
+    /// Walking depth-first needs a directory per level, so the budget is exceeded instead of
+    /// waiting forever when all threads that could release something are waiting as well.
+    pub(crate) fn acquire(self: &Arc<Self>) -> FdPermit {
+        let mut state = self.lock();
+        while state.in_use >= self.limit && state.waiting + 1 < self.threads - state.idle {
+            state.waiting += 1;
+            state = self.released.wait(state).unwrap_or_else(|e| e.into_inner());
+            state.waiting -= 1;
+        }
+        state.in_use += 1;
+        FdPermit(self.clone())
+    }
+
+    fn set_idle(&self, idle: usize) {
+        self.lock().idle = idle;
+        self.released.notify_all();
+    }
+}
+
+/// A directory counted against an `FdBudget`, returned when dropped.
+pub(crate) struct FdPermit(Arc<FdBudget>);
+
+impl Drop for FdPermit {
+    fn drop(&mut self) {
+        self.0.lock().in_use -= 1;
+        self.0.released.notify_all();
+    }
+}
+
+/// Everything `ParallelWalk::ordered` has collected so far.
+struct Collected<N> {
+    entries: Vec<(PathBuf, N)>,
+    errors: Vec<WalkError>,
+}
+
+/// Makes sure the other threads don't wait forever if the callback panics.
+struct PanicGuard<'a, N>(&'a Shared<N>);
+
+impl<'a, N> Drop for PanicGuard<'a, N> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.finish(&mut self.0.lock());
+        }
+    }
+}
+
+/// A walk that runs on multiple threads, returned by `Walk::parallel_auto`.
+///
+/// Entries are passed between threads, so running the walk requires them to be `Send`.
+pub struct ParallelWalk<N: Entry> {
+    walk: Walk<N>,
+    threads: usize,
+    fd_budget: Option<usize>,
+}
+
+impl<N: Entry> ParallelWalk<N> {
+    /// Use `threads` threads instead. At least one thread is used.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Have at most `n` directories open across all threads, to not run into `EMFILE` on wide
+    /// trees. Threads wait for another one to finish a directory before opening the next one.
+    ///
+    /// This is approximate. A depth-first walk needs one directory per level, so the limit is
+    /// exceeded on trees deeper than it allows, rather than getting stuck. Directories kept
+    /// open by entries that `f` holds on to are not counted, and neither are file descriptors
+    /// that `for_each_dir` briefly opens to tell whether an entry without `d_type` is a directory.
+    ///
+    /// ```rust
+    /// fdwalk::walk(".")
+    ///     .parallel_auto()
+    ///     .fd_budget(64)
+    ///     .for_each(|entry| {
+    ///         entry.unwrap();
+    ///     });
+    /// ```
+    pub fn fd_budget(mut self, n: usize) -> Self {
+        self.fd_budget = Some(n.max(1));
+        self
+    }
+
+    /// Walk the tree, calling `f` for each entry from whichever thread found it.
+    ///
+    /// Each thread walks depth-first on its own and hands off part of its pending entries when
+    /// another thread runs out of work. The order in which `f` sees entries is therefore
+    /// unspecified. All options of the `Walk` apply as usual.
+    pub fn for_each<F>(self, f: F)
+    where
+        N: Send,
+        F: Fn(Result<N, WalkError>) + Sync,
+    {
+        let f = &f;
+        self.run(|| move |item| f(item));
+    }
+
+    /// Walk the tree, feeding the entries each thread finds into a sink created by `make_sink`
+    /// on that thread.
+    fn run<F, S>(self, make_sink: F)
+    where
+        N: Send,
+        F: Fn() -> S + Sync,
+        S: FnMut(Result<N, WalkError>),
+    {
+        let mut walk = self.walk;
+        let threads = self.threads.max(1);
+        let budget = self.fd_budget.map(|limit| {
+            Arc::new(FdBudget {
+                state: Mutex::new(BudgetState {
+                    in_use: 0,
+                    waiting: 0,
+                    idle: 0,
+                }),
+                released: Condvar::new(),
+                limit,
+                threads,
+            })
+        });
+        walk.options.fd_budget = budget.clone();
+        let shared = Shared {
+            queue: Mutex::new(Queue {
+                pending: std::mem::take(&mut walk.walk_stack),
+                idle: 0,
+                done: false,
+            }),
+            wakeup: Condvar::new(),
+            idle: AtomicUsize::new(0),
+            threads,
+            budget,
+        };
+
+        let (shared, make_sink) = (&shared, &make_sink);
+        thread::scope(|scope| {
+            for _ in 1..threads {
+                let worker = walk.worker();
+                scope.spawn(move || run_worker(worker, shared, make_sink()));
+            }
+            run_worker(walk, shared, make_sink());
+        });
+    }
+}
+
+impl<N: Entry> ParallelWalk<N> {
+    /// Walk the tree in parallel, but yield the entries sorted by path, as if the walk had read
+    /// every directory in sorted order. Errors are yielded last, in the order they happened.
+    ///
+    /// The entire walk finishes before the first entry is yielded, and all entries are kept in
+    /// memory until then. Without `with_paths`, entries are sorted by name only, so the order
+    /// of entries with the same name is unspecified.
+    ///
+    /// ```rust
+    /// for entry in fdwalk::walk(".").with_paths().parallel_auto().ordered() {
+    ///     println!("{}", entry.unwrap().to_path().display());
+    /// }
+    /// ```
+    pub fn ordered(self) -> impl Iterator<Item = Result<N, WalkError>>
+    where
+        N: Send,
+    {
+        let collected = Mutex::new(Collected {
+            entries: Vec::new(),
+            errors: Vec::new(),
+        });
+
+        self.for_each(|item| {
+            // build the sort key on the thread that found the entry
+            let item = item.map(|x| (entry_path(&x), x));
+            let mut collected = collected.lock().unwrap_or_else(|e| e.into_inner());
+            match item {
+                Ok(x) => collected.entries.push(x),
+                Err(e) => collected.errors.push(e),
+            }
+        });
+
+        let Collected {
+            mut entries,
+            errors,
+        } = collected.into_inner().unwrap_or_else(|e| e.into_inner());
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+            .into_iter()
+            .map(|(_, x)| Ok(x))
+            .chain(errors.into_iter().map(Err))
+    }
+}
+
+impl<D: OpenProvider, E: DirentProvider> ParallelWalk<FileEntry<D, WithPath, E>> {
+    /// Walk the tree, calling `f` with each directory that contains files together with those
+    /// files, like `Walk::group_by_dir` does.
+    ///
+    /// All files of a directory are found by the same thread and passed to `f` in one call, so
+    /// that work done per directory doesn't need to be synchronized. Each directory is passed at
+    /// most once, and directories themselves are never part of a group. Each thread keeps the
+    /// files it found until it has run out of work, and only then calls `f` for them. Errors
+    /// don't stop the walk, the first one is returned once it has finished.
+    ///
+    /// ```rust
+    /// fdwalk::walk(".")
+    ///     .with_paths()
+    ///     .parallel_auto()
+    ///     .for_each_dir(|dir, files| {
+    ///         println!("{}: {} files", dir.display(), files.len());
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn for_each_dir<F>(mut self, f: F) -> Result<(), WalkError>
+    where
+        FileEntry<D, WithPath, E>: Send,
+        F: Fn(&Path, &[FileEntry<D, WithPath, E>]) + Sync,
+    {
+        self.walk.options.files_first = true;
+        self.walk.options.include_dirs = false;
+        let first_error = Mutex::new(None);
+
+        self.run(|| {
+            let mut batcher = DirBatcher {
+                f: &f,
+                first_error: &first_error,
+                groups: HashMap::new(),
+            };
+            move |item| batcher.push(item)
+        });
+
+        match first_error.into_inner().unwrap_or_else(|e| e.into_inner()) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Collects the files of one directory for `ParallelWalk::for_each_dir`.
+struct DirBatcher<'a, F, D, E>
+where
+    F: Fn(&Path, &[FileEntry<D, WithPath, E>]),
+    D: OpenProvider,
+    E: DirentProvider,
+{
+    f: &'a F,
+    first_error: &'a Mutex<Option<WalkError>>,
+    /// The files found so far, by directory.
+    groups: HashMap<PathBuf, Vec<FileEntry<D, WithPath, E>>>,
+}
+
+impl<'a, F, D, E> DirBatcher<'a, F, D, E>
+where
+    F: Fn(&Path, &[FileEntry<D, WithPath, E>]),
+    D: OpenProvider,
+    E: DirentProvider,
+{
+    fn push(&mut self, item: Result<FileEntry<D, WithPath, E>, WalkError>) {
+        let entry = match item {
+            Ok(x) => x,
+            Err(e) => {
+                let mut first_error = self.first_error.lock().unwrap_or_else(|e| e.into_inner());
+                first_error.get_or_insert(e);
+                return;
+            }
+        };
+
+        let path = entry.to_path();
+        let parent = match path.parent() {
+            Some(x) if !x.as_os_str().is_empty() => x,
+            _ => Path::new("."),
+        };
+
+        match self.groups.get_mut(parent) {
+            Some(files) => files.push(entry),
+            None => {
+                self.groups.insert(parent.to_owned(), vec![entry]);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        for (dir, files) in self.groups.drain() {
+            (self.f)(&dir, &files);
+        }
+    }
+}
+
+impl<'a, F, D, E> Drop for DirBatcher<'a, F, D, E>
+where
+    F: Fn(&Path, &[FileEntry<D, WithPath, E>]),
+    D: OpenProvider,
+    E: DirentProvider,
+{
+    fn drop(&mut self) {
+        // unless `f` panicked on an earlier directory
+        if !thread::panicking() {
+            self.flush();
+        }
+    }
+}
+
+impl<N: Entry> Walk<N> {
+    /// Walk the tree with `threads` threads, see `ParallelWalk::for_each`.
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// let files = AtomicU64::new(0);
+    /// fdwalk::walk(".").for_each_parallel(4, |entry| {
+    ///     entry.unwrap();
+    ///     files.fetch_add(1, Ordering::Relaxed);
+    /// });
+    /// println!("{} files", files.into_inner());
+    /// ```
+    pub fn for_each_parallel<F>(self, threads: usize, f: F)
+    where
+        N: Send,
+        F: Fn(Result<N, WalkError>) + Sync,
+    {
+        self.parallel_auto().threads(threads).for_each(f)
+    }
+
+    /// Walk on as many threads as there are CPUs, according to
+    /// `std::thread::available_parallelism`.
+    pub fn parallel_auto(self) -> ParallelWalk<N> {
+        let threads = thread::available_parallelism().map_or(1, |x| x.get());
+        ParallelWalk {
+            walk: self,
+            threads,
+            fd_budget: None,
+        }
+    }
+
+    /// A walk with the same options and nothing to do.
+    fn worker(&self) -> Self {
+        Walk {
+            path: self.path.clone(),
+            root_dir: self.root_dir.clone(),
+            options: self.options.clone(),
+            walk_stack: Vec::new(),
+            files_buf: Vec::new(),
+            deferred_errors: VecDeque::new(),
+            last_dir: None,
+            visited_dirs: 0,
+            skipped: 0,
+            scratch: None,
+        }
+    }
+}
+
+fn run_worker<N: Entry, S: FnMut(Result<N, WalkError>)>(
+    mut walk: Walk<N>,
+    shared: &Shared<N>,
+    mut sink: S,
+) {
+    let _guard = PanicGuard(shared);
+
+    loop {
+        while let Some(item) = walk.next() {
+            sink(item);
+
+            // the bottom of the stack is closest to the root, and so likely the most work. With
+            // `files_first`, only the top holds files, which stay on this thread so that
+            // `for_each_dir` sees all of a directory at once.
+            if shared.idle.load(Ordering::Relaxed) > 0 && walk.walk_stack.len() > 1 {
+                let dirs = walk.walk_stack.iter().take_while(|x| !x.file).count();
+                let half = cmp::min(walk.walk_stack.len() / 2, dirs);
+                if half > 0 {
+                    let mut queue = shared.lock();
+                    queue.pending.extend(walk.walk_stack.drain(..half));
+                    shared.wakeup.notify_all();
+                }
+            }
+        }
+
+        let mut queue = shared.lock();
+        loop {
+            if queue.done {
+                return;
+            }
+            if walk.is_cancelled() {
+                shared.finish(&mut queue);
+                return;
+            }
+            if let Some(pending) = queue.pending.pop() {
+                walk.walk_stack.push(pending);
+                break;
+            }
+
+            queue.idle += 1;
+            if queue.idle == shared.threads {
+                // nobody is left who could produce more work
+                shared.finish(&mut queue);
+                return;
+            }
+            shared.set_idle(queue.idle);
+            queue = shared.wakeup.wait(queue).unwrap_or_else(|e| e.into_inner());
+            queue.idle -= 1;
+            shared.set_idle(queue.idle);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::ops::Deref;
+use std::os::unix::ffi::OsStrExt;
+
+/// Names up to this length are stored inline.
+const INLINE_CAP: usize = 23;
+
+/// An owned file name that avoids a heap allocation for short names.
+///
+/// Most file names are short, and a tree of millions of files would otherwise need one
+/// allocation per name.
+#[derive(Clone)]
+pub(crate) enum Segment {
+    Inline { len: u8, buf: [u8; INLINE_CAP] },
+    Heap(Box<[u8]>),
+}
+
+impl Segment {
+    pub(crate) fn as_os_str(&self) -> &OsStr {
+        let bytes = match self {
+            Segment::Inline { len, buf } => &buf[..*len as usize],
+            Segment::Heap(bytes) => bytes,
+        };
+        OsStr::from_bytes(bytes)
+    }
+}
+
+impl From<&OsStr> for Segment {
+    fn from(name: &OsStr) -> Self {
+        let bytes = name.as_bytes();
+        if bytes.len() <= INLINE_CAP {
+            let mut buf = [0; INLINE_CAP];
+            buf[..bytes.len()].copy_from_slice(bytes);
+            Segment::Inline {
+                len: bytes.len() as u8,
+                buf,
+            }
+        } else {
+            Segment::Heap(bytes.into())
+        }
+    }
+}
+
+impl Deref for Segment {
+    type Target = OsStr;
+
+    fn deref(&self) -> &OsStr {
+        self.as_os_str()
+    }
+}
+
+impl fmt::Debug for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_os_str().fmt(f)
+    }
+}
@@ -0,0 +1,147 @@
+use std::ops::ControlFlow;
+
+use crate::fileentry::FileEntry;
+use crate::{Entry, Walk, WalkError};
+
+/// What to do with a directory passed to `Visitor::visit_dir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirDecision {
+    /// Visit the directory's contents.
+    Descend,
+    /// Don't visit anything below this directory.
+    Skip,
+}
+
+/// What to do with an entry passed to the closure of `Walk::filter_entry_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Yield the entry, and descend into it if it is a directory.
+    Yield,
+    /// Don't yield the entry, but still descend into it if it is a directory.
+    Skip,
+    /// Neither yield the entry nor descend into it.
+    Prune,
+}
+
+/// Callbacks for `Walk::visit`, as an alternative to iterating.
+pub trait Visitor<N = FileEntry> {
+    /// Called for everything that is not a directory. Return `ControlFlow::Break` to stop the
+    /// walk.
+    fn visit_file(&mut self, entry: &N) -> ControlFlow<()>;
+
+    /// Called for each directory, including the root, before its contents are visited.
+    fn visit_dir(&mut self, _entry: &N) -> DirDecision {
+        DirDecision::Descend
+    }
+}
+
+impl<N: Entry> Walk<N> {
+    /// Walk the tree, passing each entry to `visitor`. Stops at the first error.
+    ///
+    /// Directories are always passed to `Visitor::visit_dir`, regardless of `include_dirs`.
+    ///
+    /// ```rust
+    /// use std::ops::ControlFlow;
+    /// use fdwalk::{fileentry::FileEntry, DirDecision, Visitor};
+    ///
+    /// struct SkipHidden(usize);
+    ///
+    /// impl Visitor for SkipHidden {
+    ///     fn visit_file(&mut self, _entry: &FileEntry) -> ControlFlow<()> {
+    ///         self.0 += 1;
+    ///         ControlFlow::Continue(())
+    ///     }
+    ///
+    ///     fn visit_dir(&mut self, entry: &FileEntry) -> DirDecision {
+    ///         if entry.name_bytes().starts_with(b".") && entry.name_bytes() != b"." {
+    ///             DirDecision::Skip
+    ///         } else {
+    ///             DirDecision::Descend
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut visitor = SkipHidden(0);
+    /// fdwalk::walk(".").visit(&mut visitor).unwrap();
+    /// ```
+    pub fn visit<V: Visitor<N>>(mut self, visitor: &mut V) -> Result<(), WalkError> {
+        self.options.include_dirs = true;
+
+        while let Some(entry) = self.next() {
+            let entry = entry?;
+            if self.last_dir.is_some() {
+                if visitor.visit_dir(&entry) == DirDecision::Skip {
+                    self.skip_last_dir();
+                }
+            } else if visitor.visit_file(&entry).is_break() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decide for each entry whether to yield it and whether to descend into it.
+    ///
+    /// Directories are passed to `f` regardless of `include_dirs`, so that they can be pruned,
+    /// but only yielded with it.
+    ///
+    /// ```rust
+    /// use fdwalk::FilterDecision;
+    ///
+    /// let walk = fdwalk::walk(".").filter_entry_with(|entry| {
+    ///     if entry.name_bytes() == b"target" {
+    ///         FilterDecision::Prune
+    ///     } else {
+    ///         FilterDecision::Yield
+    ///     }
+    /// });
+    /// ```
+    pub fn filter_entry_with<F: FnMut(&N) -> FilterDecision>(
+        mut self,
+        f: F,
+    ) -> impl Iterator<Item = Result<N, WalkError>> {
+        let include_dirs = self.options.include_dirs;
+        self.options.include_dirs = true;
+        FilterEntry {
+            walk: self,
+            include_dirs,
+            f,
+        }
+    }
+
+    /// Drop the not yet visited contents of the directory yielded last.
+    fn skip_last_dir(&mut self) {
+        if let Some(len) = self.last_dir.take() {
+            self.walk_stack.truncate(len);
+        }
+    }
+}
+
+struct FilterEntry<N: Entry, F> {
+    walk: Walk<N>,
+    /// Whether the user asked for directories, they are always needed to prune them.
+    include_dirs: bool,
+    f: F,
+}
+
+impl<N: Entry, F: FnMut(&N) -> FilterDecision> Iterator for FilterEntry<N, F> {
+    type Item = Result<N, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.walk.next()? {
+                Ok(x) => x,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let is_dir = self.walk.last_dir.is_some();
+            match (self.f)(&entry) {
+                FilterDecision::Yield if is_dir && !self.include_dirs => (),
+                FilterDecision::Yield => return Some(Ok(entry)),
+                FilterDecision::Skip => (),
+                FilterDecision::Prune => self.walk.skip_last_dir(),
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Walk a tree while another thread keeps creating and deleting parts of it. Entries that vanish
+//! mid-walk should be skipped, and the walk should neither panic nor get stuck.
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::TempDir;
+use fdwalk::WalkError;
+use nix::errno::Errno;
+
+/// How long to keep walking.
+const CHURN: Duration = Duration::from_secs(2);
+/// How long the walks may take to notice that they are done, on top of `CHURN`.
+const GRACE: Duration = Duration::from_secs(60);
+
+fn populate(root: &Path, round: u64) {
+    for i in 0..20 {
+        let dir = root
+            .join(format!("d{}", (round + i) % 7))
+            .join(format!("s{}", i % 5));
+        let _ = fs::create_dir_all(&dir);
+        for j in 0..10 {
+            let _ = fs::write(dir.join(format!("f{}", j)), b"x");
+        }
+    }
+}
+
+fn remove(root: &Path, round: u64) {
+    let _ = fs::remove_dir_all(root.join(format!("d{}", round % 7)));
+}
+
+/// Walk `root` in every way that matters until `CHURN` is over, returning all errors.
+fn walk_repeatedly(root: PathBuf) -> Vec<WalkError> {
+    let errors = Mutex::new(Vec::new());
+    let report = |e| errors.lock().unwrap().push(e);
+
+    let deadline = Instant::now() + CHURN;
+    while Instant::now() < deadline {
+        let walk = || fdwalk::walk(&root).with_paths().with_open();
+
+        for entry in walk().include_dirs() {
+            if let Err(e) = entry {
+                report(e);
+            }
+        }
+        for group in walk().group_by_dir() {
+            if let Err(e) = group {
+                report(e);
+            }
+        }
+        for entry in walk().report_unreadable_dirs().include_dirs() {
+            if let Err(e) = entry {
+                report(e);
+            }
+        }
+        if let Err(e) = walk().count_files() {
+            report(e);
+        }
+        walk().for_each_parallel(4, |entry| {
+            if let Err(e) = entry {
+                report(e);
+            }
+        });
+    }
+
+    errors.into_inner().unwrap()
+}
+
+#[test]
+fn concurrent_deletes() {
+    let tmp = TempDir::new();
+    let root = tmp.path().to_owned();
+    populate(&root, 0);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let churner = {
+        let (root, stop) = (root.clone(), stop.clone());
+        thread::spawn(move || {
+            let mut round = 0;
+            while !stop.load(Ordering::Relaxed) {
+                round += 1;
+                populate(&root, round);
+                remove(&root, round);
+            }
+        })
+    };
+
+    let (send, recv) = mpsc::channel();
+    thread::spawn(move || send.send(walk_repeatedly(root)).unwrap());
+    let errors = recv.recv_timeout(CHURN + GRACE);
+
+    stop.store(true, Ordering::Relaxed);
+    churner.join().unwrap();
+
+    let errors = errors.expect("the walk did not finish");
+    for e in errors {
+        assert!(
+            matches!(e, WalkError::Sys(Errno::ENOENT)),
+            "unexpected error: {}",
+            e
+        );
+    }
+}
@@ -0,0 +1,69 @@
+// every test crate includes this, but not all of them use everything
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fdwalk::WalkError;
+
+/// A directory below `std::env::temp_dir()` that is removed again when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "fdwalk-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Create an empty file at `path` relative to the directory, and the directories above it.
+    pub fn touch(&self, path: &str) {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"").unwrap();
+    }
+
+    /// Create a symlink at `path` relative to the directory, pointing to `target`.
+    pub fn symlink(&self, target: &str, path: &str) {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::os::unix::fs::symlink(target, path).unwrap();
+    }
+
+    /// Create a directory at `path` relative to the directory, and the directories above it.
+    pub fn mkdir(&self, path: &str) {
+        fs::create_dir_all(self.0.join(path)).unwrap();
+    }
+
+    /// The sorted paths yielded by `walk`, relative to the directory, failing on any error.
+    pub fn relative_paths(
+        &self,
+        paths: impl Iterator<Item = Result<PathBuf, WalkError>>,
+    ) -> Vec<String> {
+        let mut rv = paths
+            .map(|path| {
+                let path = path.unwrap();
+                let path = path.strip_prefix(&self.0).unwrap();
+                path.to_str().unwrap().to_owned()
+            })
+            .collect::<Vec<_>>();
+        rv.sort();
+        rv
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
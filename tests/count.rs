@@ -0,0 +1,149 @@
+mod common;
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Arc;
+
+use common::TempDir;
+use fdwalk::{Entry, Walk, WalkCounts};
+
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.touch("x");
+    tmp.touch("a/y.txt");
+    tmp.touch("a/b/z.txt");
+    tmp.touch("a/b/w");
+    tmp.touch("c/v.txt");
+    tmp.symlink("nowhere", "dangling");
+    tmp.symlink("a/b", "link");
+    tmp
+}
+
+fn total(counts: WalkCounts) -> u64 {
+    counts.files + counts.dirs + counts.symlinks + counts.other
+}
+
+/// Check that `count_files` counts what iterating the same walk yields.
+fn assert_same_count<N: Entry>(walk: impl Fn() -> Walk<N>) -> WalkCounts {
+    let entries = walk().collect::<Result<Vec<_>, _>>().unwrap();
+    let counts = walk().count_files().unwrap();
+    assert_eq!(total(counts), entries.len() as u64, "{:?}", counts);
+    counts
+}
+
+#[test]
+fn default_options() {
+    let tmp = tree();
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()));
+    assert_eq!(
+        counts,
+        WalkCounts {
+            files: 5,
+            dirs: 0,
+            symlinks: 0,
+            other: 0,
+        }
+    );
+}
+
+#[test]
+fn report_skipped_symlinks() {
+    let tmp = tree();
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).report_skipped_symlinks());
+    assert_eq!(counts.symlinks, 2);
+}
+
+#[test]
+fn include_dirs() {
+    let tmp = tree();
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).include_dirs());
+    // the root, a, a/b and c
+    assert_eq!(counts.dirs, 4);
+}
+
+#[test]
+fn follow_symlinks() {
+    let tmp = tree();
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).follow_symlinks().include_dirs());
+    assert_eq!(counts.files, 7);
+    assert_eq!(counts.dirs, 5);
+    assert_eq!(counts.symlinks, 0);
+}
+
+#[test]
+fn max_depth() {
+    let tmp = tree();
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).max_depth(0));
+    assert_eq!(total(counts), 0);
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).max_depth(0).include_dirs());
+    assert_eq!(counts.dirs, 1);
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).max_depth(1).include_dirs());
+    assert_eq!(counts.files, 1);
+    assert_eq!(counts.dirs, 3);
+}
+
+#[test]
+fn name_filters_and_globs() {
+    let tmp = tree();
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).name_suffix(b".txt"));
+    assert_eq!(counts.files, 3);
+    let counts = assert_same_count(|| {
+        fdwalk::walk(tmp.path())
+            .include_dirs()
+            .include_glob("a/**")
+            .unwrap()
+    });
+    // `**` also matches nothing, so a itself is counted
+    assert_eq!(counts.files, 3);
+    assert_eq!(counts.dirs, 2);
+}
+
+#[test]
+fn stat_filters() {
+    let tmp = tree();
+    let ino = fs::metadata(tmp.path().join("a")).unwrap();
+    let skipped = Arc::new(HashSet::from([(ino.dev(), ino.ino())]));
+    let counts = assert_same_count(|| {
+        fdwalk::walk(tmp.path())
+            .with_open()
+            .include_dirs()
+            .skip_inodes(skipped.clone())
+    });
+    // only the root, c, and their entries
+    assert_eq!(counts.files, 2);
+    assert_eq!(counts.dirs, 2);
+
+    let counts = assert_same_count(|| {
+        fdwalk::walk(tmp.path())
+            .with_open()
+            .include_dirs()
+            .mode_matches(0o170000, 0o040000)
+    });
+    assert_eq!(total(counts), counts.dirs);
+}
+
+#[test]
+fn max_entries_per_dir() {
+    let tmp = tree();
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).max_entries_per_dir(1));
+    assert!(total(counts) < 7);
+}
+
+#[test]
+fn dot_entries() {
+    let tmp = tree();
+    let counts = assert_same_count(|| fdwalk::walk(tmp.path()).include_dot_entries());
+    assert_eq!(counts.files, 5);
+    // `.` and `..` of the root, a, a/b and c
+    assert_eq!(counts.dirs, 8);
+}
+
+#[test]
+fn count_only_with_name_filters() {
+    let tmp = tree();
+    let walk = || fdwalk::walk_with::<fdwalk::CountOnly, _>(tmp.path());
+    assert_eq!(walk().name_suffix(b".txt").count(), 3);
+    assert_eq!(walk().include_glob("a/**/*.txt").unwrap().count(), 2);
+    assert_same_count(|| walk().name_prefix(b"w"));
+}
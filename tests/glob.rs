@@ -0,0 +1,92 @@
+mod common;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use common::TempDir;
+
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.touch("src/lib.rs");
+    tmp.touch("src/a/d.txt");
+    tmp.touch("src/a/b/c.rs");
+    tmp.touch("docs/x.rs");
+    tmp.touch("target/debug/y.rs");
+    tmp
+}
+
+/// The paths yielded for `patterns`, and the directories that were read to find them.
+fn glob(tmp: &TempDir, patterns: &[&str], include_dirs: bool) -> (Vec<String>, Vec<String>) {
+    let entered = Arc::new(Mutex::new(Vec::<PathBuf>::new()));
+    let mut walk = fdwalk::walk(tmp.path()).with_paths().on_dir_enter_id({
+        let entered = entered.clone();
+        move |_, _, path| entered.lock().unwrap().push(path.to_owned())
+    });
+    if include_dirs {
+        walk = walk.include_dirs();
+    }
+    for pattern in patterns {
+        walk = walk.include_glob(pattern).unwrap();
+    }
+
+    let paths = tmp.relative_paths(walk.paths());
+    let entered = entered.lock().unwrap().clone();
+    (paths, tmp.relative_paths(entered.into_iter().map(Ok)))
+}
+
+#[test]
+fn prunes_directories_that_cannot_match() {
+    let tmp = tree();
+    let (paths, entered) = glob(&tmp, &["src/**/*.rs"], false);
+    assert_eq!(paths, ["src/a/b/c.rs", "src/lib.rs"]);
+    // docs and target are never read
+    assert_eq!(entered, ["", "src", "src/a", "src/a/b"]);
+
+    let (paths, entered) = glob(&tmp, &["src/a/*"], true);
+    assert_eq!(paths, ["src/a/b", "src/a/d.txt"]);
+    // src/a/b matches, but nothing in it can
+    assert_eq!(entered, ["", "src", "src/a"]);
+}
+
+#[test]
+fn double_star() {
+    let tmp = tree();
+    let (paths, _) = glob(&tmp, &["**/*.rs"], false);
+    assert_eq!(
+        paths,
+        [
+            "docs/x.rs",
+            "src/a/b/c.rs",
+            "src/lib.rs",
+            "target/debug/y.rs"
+        ]
+    );
+
+    // `**` also matches no components at all
+    let (paths, _) = glob(&tmp, &["src/**/lib.rs", "**/debug/**"], false);
+    assert_eq!(paths, ["src/lib.rs", "target/debug/y.rs"]);
+
+    let (paths, _) = glob(&tmp, &["src/**"], true);
+    assert_eq!(
+        paths,
+        [
+            "src",
+            "src/a",
+            "src/a/b",
+            "src/a/b/c.rs",
+            "src/a/d.txt",
+            "src/lib.rs"
+        ]
+    );
+}
+
+#[test]
+fn case_insensitive() {
+    let tmp = tree();
+    let walk = fdwalk::walk(tmp.path())
+        .with_paths()
+        .case_insensitive()
+        .include_glob("SRC/*.RS")
+        .unwrap();
+    assert_eq!(tmp.relative_paths(walk.paths()), ["src/lib.rs"]);
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use common::TempDir;
+use fdwalk::{Entry, WalkError};
+use nix::errno::Errno;
+use nix::unistd::geteuid;
+
+/// The groups relative to the temp dir, in the order they are yielded, with sorted file names.
+fn groups<N: Entry>(
+    tmp: &TempDir,
+    groups: impl Iterator<Item = Result<(PathBuf, Vec<N>), WalkError>>,
+) -> Vec<(String, Vec<String>)> {
+    groups
+        .map(|group| {
+            let (dir, files) = group.unwrap();
+            let dir = dir.strip_prefix(tmp.path()).unwrap().to_str().unwrap();
+            let mut files = files
+                .iter()
+                .map(|x| x.segment().to_str().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            files.sort();
+            (dir.to_owned(), files)
+        })
+        .collect()
+}
+
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.touch("x.txt");
+    tmp.touch("a/y");
+    tmp.touch("a/b/z.txt");
+    tmp.touch("a/b/w");
+    tmp.mkdir("a/empty");
+    tmp.touch("c/v.txt");
+    tmp
+}
+
+fn group(dir: &str, files: &[&str]) -> (String, Vec<String>) {
+    (
+        dir.to_owned(),
+        files.iter().map(|x| (*x).to_owned()).collect(),
+    )
+}
+
+fn position(groups: &[(String, Vec<String>)], dir: &str) -> usize {
+    groups.iter().position(|(x, _)| x == dir).unwrap()
+}
+
+#[test]
+fn directories_after_their_subdirectories() {
+    let tmp = tree();
+    let mut groups = groups(&tmp, fdwalk::walk(tmp.path()).with_paths().group_by_dir());
+
+    assert!(position(&groups, "a/b") < position(&groups, "a"));
+    assert!(position(&groups, "a/empty") < position(&groups, "a"));
+    // the root comes last
+    assert_eq!(groups.pop().unwrap(), group("", &["x.txt"]));
+
+    groups.sort();
+    assert_eq!(
+        groups,
+        [
+            group("a", &["y"]),
+            group("a/b", &["w", "z.txt"]),
+            group("a/empty", &[]),
+            group("c", &["v.txt"]),
+        ]
+    );
+}
+
+#[test]
+fn name_filters() {
+    let tmp = tree();
+    let walk = fdwalk::walk(tmp.path()).with_paths().name_suffix(b".txt");
+    let mut groups = groups(&tmp, walk.group_by_dir());
+    groups.sort();
+    // the directories themselves don't end in `.txt`, so only those with files are yielded
+    assert_eq!(
+        groups,
+        [
+            group("", &["x.txt"]),
+            group("a/b", &["z.txt"]),
+            group("c", &["v.txt"]),
+        ]
+    );
+}
+
+#[test]
+fn unreadable_subdirectory() {
+    if geteuid().is_root() {
+        eprintln!("skipped, root can read any directory");
+        return;
+    }
+
+    let tmp = tree();
+    let locked = tmp.path().join("a/b");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+    for walk in [
+        fdwalk::walk(tmp.path()).with_paths(),
+        fdwalk::walk(tmp.path())
+            .with_paths()
+            .report_unreadable_dirs(),
+    ] {
+        let (groups, errors): (Vec<_>, Vec<_>) = walk.group_by_dir().partition(|x| x.is_ok());
+        assert_eq!(
+            errors.into_iter().map(|x| x.err()).collect::<Vec<_>>(),
+            [Some(WalkError::Sys(Errno::EACCES))]
+        );
+
+        let mut groups = self::groups(&tmp, groups.into_iter());
+        groups.sort();
+        // a/b is neither a group nor one of the files of a
+        assert_eq!(
+            groups,
+            [
+                group("", &["x.txt"]),
+                group("a", &["y"]),
+                group("a/empty", &[]),
+                group("c", &["v.txt"]),
+            ]
+        );
+    }
+
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+}
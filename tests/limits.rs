@@ -0,0 +1,51 @@
+mod common;
+
+use common::TempDir;
+use fdwalk::WalkError;
+
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    for i in 0..5 {
+        tmp.touch(&format!("big/{}", i));
+    }
+    tmp.touch("small/x");
+    tmp
+}
+
+#[test]
+fn max_entries_per_dir() {
+    let tmp = tree();
+    let paths = fdwalk::walk(tmp.path().join("big"))
+        .with_paths()
+        .max_entries_per_dir(2)
+        .collect_paths()
+        .unwrap();
+    assert_eq!(paths.len(), 2);
+    assert!(paths
+        .iter()
+        .all(|x| x.parent() == Some(&tmp.path().join("big"))));
+}
+
+#[test]
+fn report_truncated_dirs() {
+    let tmp = tree();
+    let walk = fdwalk::walk(tmp.path())
+        .with_paths()
+        .max_entries_per_dir(2)
+        .report_truncated_dirs();
+    let (paths, errors): (Vec<_>, Vec<_>) = walk.paths().partition(|x| x.is_ok());
+    // the root has exactly two entries, which is not too many
+    let paths = tmp.relative_paths(paths.into_iter());
+    assert_eq!(paths.len(), 3);
+    assert!(paths.contains(&"small/x".to_owned()));
+    assert_eq!(
+        errors,
+        [Err(WalkError::DirTruncated(tmp.path().join("big")))]
+    );
+
+    let errors = fdwalk::walk(tmp.path())
+        .max_entries_per_dir(2)
+        .filter_map(Result::err)
+        .count();
+    assert_eq!(errors, 0);
+}
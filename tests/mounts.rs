@@ -0,0 +1,47 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use common::TempDir;
+
+#[test]
+fn plain_directories() {
+    let tmp = TempDir::new();
+    tmp.mkdir("a/b");
+
+    for root in [tmp.path().to_owned(), tmp.path().join("a")] {
+        for entry in fdwalk::walk(&root).with_open().include_dirs() {
+            assert!(!entry.unwrap().is_mount_point().unwrap());
+        }
+    }
+}
+
+#[test]
+fn proc_below_root() {
+    let dev = |path| fs::metadata(path).map(|x| x.dev());
+    if dev("/proc").is_err() || dev("/proc").ok() == dev("/").ok() {
+        eprintln!("skipped, /proc is not mounted");
+        return;
+    }
+
+    let proc = fdwalk::walk("/")
+        .with_open()
+        .with_paths()
+        .include_dirs()
+        .max_depth(1)
+        .filter_map(Result::ok)
+        .find(|x| x.to_path() == Path::new("/proc"))
+        .unwrap();
+    assert!(proc.is_mount_point().unwrap());
+
+    // and the root of a walk started there, which has no parent to compare with
+    let root = fdwalk::walk("/proc")
+        .with_open()
+        .include_dirs()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(root.is_mount_point().unwrap());
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use std::fs;
+
+use common::TempDir;
+use fdwalk::WalkError;
+
+#[test]
+fn static_tree_with_dangling_symlink() {
+    let tmp = TempDir::new();
+    tmp.touch("a/x");
+    tmp.symlink("nowhere", "dangling");
+    tmp.symlink("a", "link");
+
+    let walk = fdwalk::walk(tmp.path())
+        .with_paths()
+        .follow_symlinks()
+        .detect_mutation();
+    // the dangling symlink is skipped like without `detect_mutation`
+    assert_eq!(tmp.relative_paths(walk.paths()), ["a/x", "link/x"]);
+}
+
+#[test]
+fn deleted_directory() {
+    let tmp = TempDir::new();
+    tmp.touch("a/x");
+
+    let mut walk = fdwalk::walk(tmp.path())
+        .with_paths()
+        .include_dirs()
+        .detect_mutation();
+    let root = walk.next().unwrap().unwrap();
+    assert_eq!(root.to_path(), tmp.path());
+
+    // `a` has been read as part of the root, but not opened yet
+    fs::remove_dir_all(tmp.path().join("a")).unwrap();
+    match walk.next() {
+        Some(Err(WalkError::TreeChanged(path))) => assert_eq!(path, tmp.path().join("a")),
+        x => panic!(
+            "expected TreeChanged, got {:?}",
+            x.map(|x| x.map(|x| x.to_path()))
+        ),
+    }
+    assert!(walk.next().is_none());
+}
+
+#[test]
+fn deleted_file() {
+    let tmp = TempDir::new();
+    tmp.touch("a/x");
+    tmp.touch("y");
+
+    let mut walk = fdwalk::walk(tmp.path())
+        .with_paths()
+        .include_dirs()
+        .detect_mutation();
+    walk.next().unwrap().unwrap();
+
+    // files are never opened, so this goes unnoticed and `y` is still yielded
+    fs::remove_file(tmp.path().join("y")).unwrap();
+    assert_eq!(tmp.relative_paths(walk.paths()), ["a", "a/x", "y"]);
+}
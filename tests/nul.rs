@@ -0,0 +1,74 @@
+mod common;
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::sync::Arc;
+
+use common::TempDir;
+use fdwalk::{Dirent, Entry, WalkError};
+use nix::dir::Dir;
+use nix::errno::Errno;
+
+#[test]
+fn nul_in_root() {
+    let mut walk = fdwalk::walk("a\0b");
+    assert!(matches!(
+        walk.next(),
+        Some(Err(WalkError::Sys(Errno::EINVAL)))
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn nul_in_root_with_openat2() {
+    let mut walk = fdwalk::walk("a\0b").no_magiclinks();
+    assert!(matches!(
+        walk.next(),
+        Some(Err(WalkError::Sys(Errno::EINVAL)))
+    ));
+}
+
+/// An entry that appends a NUL byte to the name of every child, like a broken filesystem might.
+struct Mangled(OsString);
+
+impl Entry for Mangled {
+    fn root(_parent_dir: Option<&Arc<Dir>>, segment: &OsStr) -> Self {
+        Mangled(segment.to_owned())
+    }
+
+    fn segment(&self) -> &OsStr {
+        &self.0
+    }
+
+    fn new_child(&self, _parent_dir: &Arc<Dir>, segment: &OsStr, _dirent: &Dirent) -> Self {
+        let mut name = segment.as_bytes().to_vec();
+        name.push(0);
+        Mangled(OsStr::from_bytes(&name).to_owned())
+    }
+}
+
+#[test]
+fn nul_in_child() {
+    let tmp = TempDir::new();
+    tmp.touch("dir/x");
+
+    let errors = fdwalk::walk_with::<Mangled, _>(tmp.path())
+        .filter_map(Result::err)
+        .collect::<Vec<_>>();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], WalkError::Sys(Errno::EINVAL)));
+}
+
+#[test]
+fn nul_in_child_with_openat2() {
+    let tmp = TempDir::new();
+    tmp.touch("dir/x");
+
+    // the same path `walk_sandboxed` opens children through
+    let errors = fdwalk::walk_with::<Mangled, _>(tmp.path())
+        .no_magiclinks()
+        .filter_map(Result::err)
+        .collect::<Vec<_>>();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], WalkError::Sys(Errno::EINVAL)));
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use common::TempDir;
+use fdwalk::WalkError;
+
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.touch("x");
+    tmp.touch("a/y");
+    tmp.touch("a/b/z");
+    tmp.touch("a/b/w");
+    tmp.symlink("nowhere", "dangling");
+    tmp.symlink("a/b", "link");
+    tmp
+}
+
+#[test]
+fn for_each_dir_sees_each_directory_once() {
+    let tmp = tree();
+
+    for &threads in &[1, 4] {
+        let groups = Mutex::new(Vec::new());
+        fdwalk::walk(tmp.path())
+            .with_paths()
+            .follow_dir_symlinks()
+            .parallel_auto()
+            .threads(threads)
+            .for_each_dir(|dir, files| {
+                let dir = dir.strip_prefix(tmp.path()).unwrap().to_owned();
+                let mut names = files
+                    .iter()
+                    .map(|x| x.to_path().file_name().unwrap().to_owned())
+                    .collect::<Vec<_>>();
+                names.sort();
+                groups.lock().unwrap().push((dir, names));
+            })
+            .unwrap();
+
+        let mut groups = groups.into_inner().unwrap();
+        groups.sort();
+        let expected: Vec<(&Path, Vec<&str>)> = vec![
+            (Path::new(""), vec!["dangling", "x"]),
+            (Path::new("a"), vec!["y"]),
+            (Path::new("a/b"), vec!["w", "z"]),
+            (Path::new("link"), vec!["w", "z"]),
+        ];
+        assert_eq!(groups.len(), expected.len(), "{:?}", groups);
+        for ((dir, names), (expected_dir, expected_names)) in groups.iter().zip(expected) {
+            assert_eq!(dir, expected_dir);
+            assert_eq!(names, &expected_names);
+        }
+    }
+}
+
+#[test]
+fn fd_budget() {
+    let tmp = TempDir::new();
+    for i in 0..20 {
+        tmp.touch(&format!("d{}/e/f/x", i));
+    }
+
+    // a budget of one directory is less than a single thread needs for this depth, which
+    // must not get the walk stuck
+    for &budget in &[1, 4] {
+        let paths = Mutex::new(Vec::new());
+        fdwalk::walk(tmp.path())
+            .with_paths()
+            .parallel_auto()
+            .threads(4)
+            .fd_budget(budget)
+            .for_each(|entry| paths.lock().unwrap().push(entry.map(|x| x.to_path())));
+
+        let paths = tmp.relative_paths(paths.into_inner().unwrap().into_iter());
+        let mut expected = (0..20).map(|i| format!("d{}/e/f/x", i)).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(paths, expected);
+    }
+}
+
+#[test]
+fn ordered() {
+    let tmp = TempDir::new();
+    tmp.touch("c");
+    tmp.touch("a-b");
+    tmp.touch("a/x");
+    tmp.touch("b/y");
+    tmp.touch("b/deep/z");
+
+    for &threads in &[1, 4] {
+        let items = fdwalk::walk(tmp.path())
+            .with_paths()
+            .include_dirs()
+            .depth_limit(2)
+            .parallel_auto()
+            .threads(threads)
+            .ordered()
+            .map(|x| x.map(|x| x.to_path()))
+            .collect::<Vec<_>>();
+
+        let mut expected = ["", "a", "a/x", "a-b", "b", "b/deep", "b/y", "c"]
+            .iter()
+            .map(|x| Ok(tmp.path().join(x)))
+            .collect::<Vec<_>>();
+        // sorted by component, so `a/x` comes before `a-b`, and errors come last
+        expected.push(Err(WalkError::MaxDepthExceeded(tmp.path().join("b/deep"))));
+        assert_eq!(items, expected);
+    }
+}
@@ -0,0 +1,34 @@
+use std::ffi::OsStr;
+
+use fdwalk::fileentry::{PathProvider, WithInternedPath, WithPath};
+
+/// Far deeper than a test thread's stack would allow for a recursive `Drop`.
+const DEPTH: usize = 100_000;
+
+fn chain<P: PathProvider>() -> P {
+    let mut node = P::path_provider(None, OsStr::new("root"));
+    for _ in 0..DEPTH {
+        node = P::path_provider(Some(&node), OsStr::new("x"));
+    }
+    node
+}
+
+#[test]
+fn drop_deep_path() {
+    drop(chain::<WithPath>());
+}
+
+#[test]
+fn drop_deep_interned_path() {
+    drop(chain::<WithInternedPath>());
+}
+
+#[test]
+fn drop_shared_deep_path() {
+    let leaf = chain::<WithPath>();
+    let sibling = WithPath::path_provider(Some(&leaf), OsStr::new("y"));
+    drop(leaf);
+    let path = WithPath::to_path(Some(&sibling)).unwrap();
+    assert_eq!(path.components().count(), DEPTH + 2);
+    drop(sibling);
+}
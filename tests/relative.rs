@@ -0,0 +1,62 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::TempDir;
+
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.touch("a/x");
+    tmp.touch("a/b/y");
+    tmp
+}
+
+fn sorted(paths: impl Iterator<Item = Result<PathBuf, fdwalk::WalkError>>) -> Vec<PathBuf> {
+    let mut paths = paths.collect::<Result<Vec<_>, _>>().unwrap();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn below_base() {
+    let tmp = tree();
+    let walk = || {
+        fdwalk::walk(tmp.path().join("a"))
+            .with_paths()
+            .include_dirs()
+    };
+
+    assert_eq!(
+        sorted(walk().relative_to(tmp.path().to_owned()).paths()),
+        ["a", "a/b", "a/b/y", "a/x"].map(PathBuf::from)
+    );
+    // the root itself becomes `.`, which is left out of the paths below it
+    assert_eq!(
+        sorted(walk().relative_to(tmp.path().join("a")).paths()),
+        [".", "b", "b/y", "x"].map(PathBuf::from)
+    );
+}
+
+#[test]
+fn not_below_base() {
+    let tmp = tree();
+    let paths = fdwalk::walk(tmp.path().join("a"))
+        .with_paths()
+        .relative_to(tmp.path().join("elsewhere"))
+        .paths();
+    assert_eq!(tmp.relative_paths(paths), ["a/b/y", "a/x"]);
+}
+
+#[test]
+fn entries_still_open_the_real_path() {
+    let tmp = tree();
+    let stats = fdwalk::walk(tmp.path().join("a"))
+        .with_paths()
+        .with_open()
+        .include_dirs()
+        .relative_to(tmp.path().to_owned())
+        .map(|x| x.unwrap().stat().map(|_| ()))
+        .collect::<Vec<_>>();
+    assert_eq!(stats.len(), 4);
+    assert!(stats.iter().all(Result::is_ok));
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use common::TempDir;
+
+#[test]
+fn multi_component_root() {
+    let tmp = TempDir::new();
+    tmp.touch("a/b/x");
+    tmp.touch("a/b/c/y");
+
+    let root = tmp.path().join("a/b");
+    let entries = fdwalk::walk(&root)
+        .with_paths()
+        .max_depth(1)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let x = &entries[0];
+    assert_eq!(x.to_path(), root.join("x"));
+    // one level below the root, however many components the root has
+    assert_eq!(x.rebase(Path::new("base")), Path::new("base/x"));
+    assert_eq!(x.parent_path().unwrap(), root);
+    assert_eq!(x.ancestor(2).unwrap(), tmp.path().join("a"));
+    assert_eq!(x.path_components().last(), Some(OsStr::new("x")));
+    assert_eq!(x.path_components().len(), root.components().count() + 1);
+}
+
+#[test]
+fn trailing_slash() {
+    let tmp = TempDir::new();
+    tmp.touch("dir/x");
+    tmp.touch("dir/sub/y");
+
+    let paths = |root: &Path| {
+        let mut paths = fdwalk::walk(root)
+            .with_paths()
+            .include_dirs()
+            .paths()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        paths.sort();
+        paths
+    };
+
+    let dir = tmp.path().join("dir");
+    let expected = vec![
+        dir.clone(),
+        dir.join("sub"),
+        dir.join("sub/y"),
+        dir.join("x"),
+    ];
+    assert_eq!(paths(&dir), expected);
+
+    let mut with_slash = dir.into_os_string();
+    with_slash.push("/");
+    assert_eq!(paths(Path::new(&with_slash)), expected);
+    with_slash.push("/");
+    assert_eq!(paths(Path::new(&with_slash)), expected);
+}
+
+#[test]
+fn filesystem_root() {
+    let paths = fdwalk::walk("/")
+        .with_paths()
+        .include_dirs()
+        .no_recurse()
+        .paths()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(paths[0], Path::new("/"));
+    assert!(paths.len() > 1);
+    for path in &paths[1..] {
+        assert_eq!(path.parent(), Some(Path::new("/")));
+        assert!(!path.as_os_str().as_bytes().starts_with(b"//"));
+    }
+}
+
+#[test]
+fn dot_relative_root() {
+    let tmp = TempDir::new();
+    tmp.touch("a/b/x");
+    // the other tests here only walk absolute paths, so they don't mind
+    std::env::set_current_dir(tmp.path()).unwrap();
+
+    let entries = fdwalk::walk("./a")
+        .with_paths()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let x = &entries[0];
+    assert_eq!(x.to_path(), Path::new("./a/b/x"));
+    assert_eq!(x.ancestor(1).unwrap(), Path::new("./a/b"));
+    assert_eq!(x.ancestor(2).unwrap(), Path::new("./a"));
+    assert_eq!(x.ancestor(3).unwrap(), Path::new("."));
+    assert_eq!(x.ancestor(4), None);
+    assert_eq!(x.ancestor(5), None);
+
+    let entries = fdwalk::walk("a")
+        .with_paths()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let x = &entries[0];
+    assert_eq!(x.ancestor(2).unwrap(), Path::new("a"));
+    assert_eq!(x.ancestor(3).unwrap(), Path::new("."));
+    assert_eq!(x.ancestor(4), None);
+}
@@ -0,0 +1,86 @@
+//! Walk while a timer keeps interrupting the walking thread with a signal whose handler is
+//! installed without `SA_RESTART`, so that interrupted syscalls fail with `EINTR`.
+//!
+//! This is the only test in this crate, since the handler and the timer are process-wide.
+
+mod common;
+
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+
+use common::TempDir;
+use nix::libc;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+/// The thread that is supposed to be interrupted.
+static WALKER: AtomicI32 = AtomicI32::new(0);
+/// Signals that arrived on `WALKER`.
+static INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn on_alarm(_: libc::c_int) {
+    let walker = WALKER.load(Ordering::Relaxed);
+    // SAFETY: `gettid`, `getpid` and `tgkill` are async-signal-safe
+    unsafe {
+        if libc::gettid() == walker {
+            INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            // the timer signals the process, it usually ends up at the main thread
+            libc::syscall(libc::SYS_tgkill, libc::getpid(), walker, libc::SIGALRM);
+        }
+    }
+}
+
+extern "C" {
+    // not exposed by the version of `libc` that `nix` uses
+    fn setitimer(
+        which: libc::c_int,
+        new: *const libc::itimerval,
+        old: *mut libc::itimerval,
+    ) -> libc::c_int;
+}
+
+fn set_timer(usec: libc::suseconds_t) {
+    let interval = libc::timeval {
+        tv_sec: 0,
+        tv_usec: usec,
+    };
+    let timer = libc::itimerval {
+        it_interval: interval,
+        it_value: interval,
+    };
+    // SAFETY: both pointers are valid for the duration of the call
+    let rv = unsafe { setitimer(libc::ITIMER_REAL, &timer, std::ptr::null_mut()) };
+    assert_eq!(rv, 0);
+}
+
+#[test]
+fn walk_with_signals() {
+    let tmp = TempDir::new();
+    for i in 0..200 {
+        for j in 0..50 {
+            tmp.touch(&format!("d{}/f{}", i, j));
+        }
+    }
+
+    WALKER.store(unsafe { libc::gettid() }, Ordering::Relaxed);
+    let action = SigAction::new(
+        SigHandler::Handler(on_alarm),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only touches atomics and calls async-signal-safe functions
+    unsafe { sigaction(Signal::SIGALRM, &action) }.unwrap();
+    set_timer(100);
+
+    for _ in 0..5 {
+        let walk = || fdwalk::walk(tmp.path()).with_open();
+        let entries = walk()
+            .include_dirs()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1 + 200 + 200 * 50);
+        assert_eq!(walk().count_files().unwrap().files, 200 * 50);
+    }
+
+    set_timer(0);
+    assert!(INTERRUPTS.load(Ordering::Relaxed) > 0);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use common::TempDir;
+
+/// `paths` relative to the temp dir, in order.
+fn relative(tmp: &TempDir, paths: impl Iterator<Item = PathBuf>) -> Vec<String> {
+    paths
+        .map(|x| {
+            x.strip_prefix(tmp.path())
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_owned()
+        })
+        .collect()
+}
+
+fn touch_at(tmp: &TempDir, path: &str, secs: u64) {
+    tmp.touch(path);
+    let file = File::options()
+        .write(true)
+        .open(tmp.path().join(path))
+        .unwrap();
+    file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap();
+}
+
+#[test]
+fn top_by_mtime() {
+    let tmp = TempDir::new();
+    touch_at(&tmp, "a", 1000);
+    touch_at(&tmp, "b/c", 3000);
+    touch_at(&tmp, "d", 2000);
+    touch_at(&tmp, "b/e", 500);
+
+    let top = |n| {
+        let entries = fdwalk::walk(tmp.path())
+            .with_open()
+            .with_paths()
+            .top_by_mtime(n)
+            .unwrap();
+        relative(&tmp, entries.iter().map(|x| x.to_path()))
+    };
+    assert_eq!(top(2), ["b/c", "d"]);
+    assert_eq!(top(10), ["b/c", "d", "a", "b/e"]);
+    assert!(top(0).is_empty());
+}
+
+#[test]
+fn largest() {
+    let tmp = TempDir::new();
+    let write = |path: &str, len| {
+        tmp.touch(path);
+        fs::write(tmp.path().join(path), vec![0; len]).unwrap();
+    };
+    write("a", 10);
+    write("d", 30);
+    write("b/c", 30);
+    write("b/e", 5);
+
+    let largest = |n| {
+        let entries = fdwalk::walk(tmp.path())
+            .with_open()
+            .with_paths()
+            .largest(n)
+            .unwrap();
+        relative(&tmp, entries.iter().map(|x| x.to_path()))
+    };
+    // the same size is ordered by path
+    assert_eq!(largest(3), ["b/c", "d", "a"]);
+    assert_eq!(largest(10), ["b/c", "d", "a", "b/e"]);
+    assert!(largest(0).is_empty());
+}
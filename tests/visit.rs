@@ -0,0 +1,116 @@
+mod common;
+
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+
+use common::TempDir;
+use fdwalk::{DirDecision, Entry, FilterDecision, Visitor};
+
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.touch("x");
+    tmp.touch("a/y");
+    tmp.touch("a/skip/z");
+    tmp.touch("a/skip/deeper/w");
+    tmp.touch("target/debug/v");
+    tmp
+}
+
+/// Records what it is called with, skips directories called `skip`, and stops after `limit`
+/// files.
+struct Recorder {
+    dirs: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+    limit: usize,
+}
+
+impl Recorder {
+    fn new(limit: usize) -> Self {
+        Recorder {
+            dirs: Vec::new(),
+            files: Vec::new(),
+            limit,
+        }
+    }
+}
+
+impl<N: Entry> Visitor<N> for Recorder {
+    fn visit_file(&mut self, entry: &N) -> ControlFlow<()> {
+        self.files.push(entry.path().unwrap());
+        if self.files.len() == self.limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+
+    fn visit_dir(&mut self, entry: &N) -> DirDecision {
+        self.dirs.push(entry.path().unwrap());
+        if entry.segment() == "skip" {
+            DirDecision::Skip
+        } else {
+            DirDecision::Descend
+        }
+    }
+}
+
+#[test]
+fn visit_skips_directories() {
+    let tmp = tree();
+    let mut recorder = Recorder::new(usize::MAX);
+    fdwalk::walk(tmp.path())
+        .with_paths()
+        .visit(&mut recorder)
+        .unwrap();
+
+    // nothing below `skip` is visited, but `skip` itself is
+    assert_eq!(
+        tmp.relative_paths(recorder.dirs.into_iter().map(Ok)),
+        ["", "a", "a/skip", "target", "target/debug"]
+    );
+    assert_eq!(
+        tmp.relative_paths(recorder.files.into_iter().map(Ok)),
+        ["a/y", "target/debug/v", "x"]
+    );
+}
+
+#[test]
+fn visit_stops_on_break() {
+    let tmp = tree();
+    let mut recorder = Recorder::new(2);
+    fdwalk::walk(tmp.path())
+        .with_paths()
+        .visit(&mut recorder)
+        .unwrap();
+    assert_eq!(recorder.files.len(), 2);
+}
+
+fn decide<N: Entry>(entry: &N) -> FilterDecision {
+    match entry.segment().to_str().unwrap() {
+        "target" => FilterDecision::Prune,
+        "a" | "z" => FilterDecision::Skip,
+        _ => FilterDecision::Yield,
+    }
+}
+
+#[test]
+fn filter_entry_with() {
+    let tmp = tree();
+    let walk = fdwalk::walk(tmp.path())
+        .with_paths()
+        .filter_entry_with(decide);
+    // `a` is skipped but still descended into, `target` is pruned
+    assert_eq!(
+        tmp.relative_paths(walk.map(|x| x.map(|x| x.to_path()))),
+        ["a/skip/deeper/w", "a/y", "x"]
+    );
+
+    let walk = fdwalk::walk(tmp.path())
+        .with_paths()
+        .include_dirs()
+        .filter_entry_with(decide);
+    assert_eq!(
+        tmp.relative_paths(walk.map(|x| x.map(|x| x.to_path()))),
+        ["", "a/skip", "a/skip/deeper", "a/skip/deeper/w", "a/y", "x"]
+    );
+}